@@ -2,6 +2,8 @@ use std::path::PathBuf;
 
 use structopt::StructOpt;
 
+use crate::fomod::{InstallMode, InstallOptions};
+
 #[derive(Debug, StructOpt)]
 #[structopt(about = "Command-line interface mod manager")]
 pub enum App {
@@ -31,7 +33,24 @@ pub enum App {
         exe: Option<PathBuf>,
     },
     #[structopt(alias = "deploy", about = "Deploy mods")]
-    Go,
+    Go {
+        #[structopt(flatten)]
+        install: InstallArgs,
+        #[structopt(
+            long,
+            short,
+            help = "The number of mods to extract and deploy at once. Defaults to the number of CPUs"
+        )]
+        jobs: Option<usize>,
+        #[structopt(
+            long,
+            short = "n",
+            help = "Show what would be extracted, deployed and removed without changing anything"
+        )]
+        dry_run: bool,
+        #[structopt(long, requires = "dry-run", help = "Show the dry run as JSON")]
+        json: bool,
+    },
     #[structopt(about = "Add mod archives to the active game")]
     Add {
         #[structopt(help = "Paths to the archive files")]
@@ -60,6 +79,14 @@ pub enum App {
         names: Vec<String>,
         #[structopt(long, help = "Enable all mods")]
         all: bool,
+        #[structopt(flatten)]
+        install: InstallArgs,
+        #[structopt(
+            long,
+            short,
+            help = "The number of mods to extract at once. Defaults to the number of CPUs"
+        )]
+        jobs: Option<usize>,
     },
     #[structopt(about = "Disable mods")]
     Disable {
@@ -70,6 +97,42 @@ pub enum App {
     },
     #[structopt(about = "List all mods")]
     Mods,
+    #[structopt(about = "List mods whose archives changed since they were extracted")]
+    Status,
+    #[structopt(about = "List files that more than one mod provides")]
+    Conflicts {
+        #[structopt(help = "Only show the conflicts of this mod. It does not need to be exact.")]
+        name: Option<String>,
+    },
+    #[structopt(about = "Hide files in a mod so that they are not deployed")]
+    Hide {
+        #[structopt(help = "The name of the mod. It does not need to be exact.")]
+        name: String,
+        #[structopt(
+            help = "Paths of files relative to the data folder. * and ? match any characters. \
+                    Lists the hidden files if none are given"
+        )]
+        patterns: Vec<String>,
+        #[structopt(long, short, help = "Show the files again")]
+        remove: bool,
+    },
+    #[structopt(about = "Make a mod provide files regardless of load order")]
+    Prefer {
+        #[structopt(help = "The name of the mod. It does not need to be exact.")]
+        name: String,
+        #[structopt(
+            help = "Paths of files relative to the data folder. * and ? match any characters. \
+                    Lists the preferred files if none are given"
+        )]
+        patterns: Vec<String>,
+        #[structopt(long, short, help = "Go back to using the load order for the files")]
+        remove: bool,
+    },
+    #[structopt(about = "Show information about a mod")]
+    Info {
+        #[structopt(help = "The name of the mod")]
+        name: String,
+    },
     #[structopt(about = "List all enabled plugs")]
     Plugins,
     #[structopt(about = "Move a mod in the load order")]
@@ -111,6 +174,11 @@ pub enum App {
     GameFolder,
     #[structopt(about = "Run the game")]
     Run,
+    #[structopt(about = "Fomod installer tools")]
+    Fomod {
+        #[structopt(subcommand)]
+        sub: FomodSubcommand,
+    },
 }
 
 #[derive(Debug, StructOpt)]
@@ -152,3 +220,49 @@ pub enum ProfileSubcommand {
         disable_new: bool,
     },
 }
+
+#[derive(Debug, StructOpt)]
+pub enum FomodSubcommand {
+    #[structopt(about = "Check a mod's Fomod config for problems")]
+    Check {
+        #[structopt(help = "The path to a mod archive or extracted mod folder")]
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub struct InstallArgs {
+    #[structopt(
+        long,
+        conflicts_with = "no-input",
+        help = "Install the recommended options of mod installers without prompting"
+    )]
+    pub defaults: bool,
+    #[structopt(
+        long,
+        help = "A TOML file of installer answers, keyed by mod, step, and group"
+    )]
+    pub answers: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "What to do when an installer needs an answer: defaults, fail, or skip"
+    )]
+    pub no_input: Option<InstallMode>,
+}
+
+impl InstallArgs {
+    pub fn options(&self) -> crate::Result<InstallOptions> {
+        let mut options = InstallOptions {
+            mode: self.no_input.unwrap_or(if self.defaults {
+                InstallMode::Defaults
+            } else {
+                InstallMode::Prompt
+            }),
+            ..Default::default()
+        };
+        if let Some(answers) = &self.answers {
+            options.load_answers(answers)?;
+        }
+        Ok(options)
+    }
+}
@@ -0,0 +1,342 @@
+mod wizard;
+pub use wizard::*;
+
+use std::{collections::HashSet, fs, path::Path};
+
+use serde_derive::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::{
+    colorln,
+    fomod::{Environment, InstallMode, InstallOptions},
+    game::FileMapping,
+    utils,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("wizard.txt line {line}: {message}")]
+    Wizard { line: usize, message: String },
+}
+
+/// The group that sub-packages are chosen in
+const SUB_PACKAGES: &str = "Sub-packages";
+
+/// Top-level folders that are part of the data folder rather than sub-packages
+const DATA_FOLDERS: &[&str] = &[
+    "bash patches",
+    "bsa",
+    "data",
+    "distantlod",
+    "docs",
+    "fomod",
+    "fonts",
+    "fose",
+    "grass",
+    "interface",
+    "lodsettings",
+    "materials",
+    "menus",
+    "meshes",
+    "music",
+    "nvse",
+    "obse",
+    "scripts",
+    "seq",
+    "shaders",
+    "skse",
+    "sound",
+    "strings",
+    "textures",
+    "trees",
+    "video",
+];
+
+/// Read a package.txt description, if there is one
+fn description(folder: &Path) -> String {
+    utils::find_case_insensitive(folder, "package.txt")
+        .and_then(|path| fs::read(path).ok())
+        .map(|bytes| String::from_utf8_lossy(&bytes).trim().to_string())
+        .unwrap_or_default()
+}
+
+/// A folder in a BAIN package that can be installed on its own
+#[derive(Debug, Clone)]
+pub struct SubPackage {
+    pub name: String,
+    pub description: String,
+    /// The names of the plugins at the top of the sub-package
+    pub espms: Vec<String>,
+}
+
+/// A complex Wrye Bash (BAIN) package
+#[derive(Debug, Clone)]
+pub struct Package {
+    pub description: String,
+    pub sub_packages: Vec<SubPackage>,
+    /// The text of the package's wizard.txt
+    pub wizard: Option<String>,
+}
+
+impl Package {
+    /// Find a complex BAIN package in an extracted mod
+    ///
+    /// A package is complex if it has a wizard or any top-level folder that starts with a
+    /// number and is not a data folder.
+    pub fn find(extracted: &Path) -> crate::Result<Option<Self>> {
+        let mut entries: Vec<_> = fs::read_dir(extracted)?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().is_dir())
+            .filter(|entry| {
+                let name = entry.file_name().to_string_lossy().to_lowercase();
+                !DATA_FOLDERS.contains(&name.as_str())
+            })
+            .collect();
+        entries.sort_by_key(|entry| entry.file_name());
+        let wizard = utils::find_case_insensitive(extracted, "wizard.txt")
+            .map(fs::read)
+            .transpose()?
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned());
+        let numbered = entries.iter().any(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(|c: char| c.is_ascii_digit())
+        });
+        if entries.is_empty() || !numbered && wizard.is_none() {
+            return Ok(None);
+        }
+        let mut sub_packages = Vec::new();
+        for entry in entries {
+            let mut espms: Vec<String> = fs::read_dir(entry.path())?
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| path.is_file() && utils::is_plugin(path))
+                .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+                .collect();
+            espms.sort();
+            sub_packages.push(SubPackage {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                description: description(&entry.path()),
+                espms,
+            });
+        }
+        Ok(Some(Package {
+            description: description(extracted),
+            sub_packages,
+            wizard,
+        }))
+    }
+    /// Get the index of a sub-package by name, ignoring case
+    pub fn sub_package(&self, name: &str) -> Option<usize> {
+        self.sub_packages
+            .iter()
+            .position(|sub| sub.name.eq_ignore_ascii_case(name))
+    }
+    /// Get the names of all sub-packages
+    pub fn names(&self) -> Vec<String> {
+        self.sub_packages
+            .iter()
+            .map(|sub| sub.name.clone())
+            .collect()
+    }
+    /// Get the sub-packages selected when there is no other input
+    ///
+    /// These are the ones that start with "00", or the first one if there are none.
+    fn default_selection(&self) -> Vec<usize> {
+        let core: Vec<usize> = (0..self.sub_packages.len())
+            .filter(|&i| self.sub_packages[i].name.starts_with("00"))
+            .collect();
+        if core.is_empty() && !self.sub_packages.is_empty() {
+            vec![0]
+        } else {
+            core
+        }
+    }
+}
+
+/// The sub-packages and plugins chosen for a BAIN package
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Selection {
+    /// The names of the selected sub-packages
+    pub sub_packages: Vec<String>,
+    /// Plugins in the selected sub-packages that are not installed
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub deselected_espms: Vec<String>,
+    /// The names of the sub-packages that were available
+    pub options: Vec<String>,
+    /// Answers given to the wizard by prompt
+    #[serde(skip_serializing_if = "indexmap::IndexMap::is_empty")]
+    pub wizard: indexmap::IndexMap<String, Vec<String>>,
+}
+
+/// Install a BAIN package, returning the files that should be installed and the selection
+///
+/// A previous selection is reused if the sub-packages have not changed. Returns `None` if
+/// the mod was skipped.
+pub fn install(
+    mod_name: &str,
+    extracted: &Path,
+    package: &Package,
+    env: &Environment,
+    options: &InstallOptions,
+    previous: Option<&Selection>,
+) -> crate::Result<Option<(Vec<FileMapping>, Selection)>> {
+    colorln!(bold, "\nInstalling {:?} (BAIN package)", mod_name);
+    if !package.description.is_empty() {
+        colorln!(dimmed, "{}", package.description);
+    }
+    let names = package.names();
+    let previous = previous.filter(|previous| {
+        let unchanged = previous.options == names;
+        if !unchanged {
+            colorln!(yellow, "The sub-packages of {:?} have changed", mod_name);
+        }
+        unchanged
+    });
+    let selection = if let Some(script) = &package.wizard {
+        let answers = previous.map(|previous| &previous.wizard);
+        let result = Wizard::parse(script)
+            .map_err(crate::Error::from)
+            .and_then(|wizard| wizard.run(mod_name, package, env, options, answers));
+        match result {
+            Ok(selection) => selection,
+            Err(crate::Error::Bain(e))
+                if matches!(options.mode, InstallMode::Prompt | InstallMode::Defaults) =>
+            {
+                colorln!(yellow, "Unable to run wizard for {:?}: {}", mod_name, e);
+                select_sub_packages(mod_name, package, options, None)?
+            }
+            Err(crate::Error::Bain(e)) if options.mode == InstallMode::Skip => {
+                colorln!(yellow, "Skipped {:?}: {}", mod_name, e);
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        }
+    } else {
+        select_sub_packages(mod_name, package, options, previous)?
+    };
+    let mut selection = if let Some(selection) = selection {
+        selection
+    } else {
+        return Ok(None);
+    };
+    selection.options = names;
+    println!("Sub-packages: {}", selection.sub_packages.join(", "));
+    if !selection.deselected_espms.is_empty() {
+        println!("Skipped plugins: {}", selection.deselected_espms.join(", "));
+    }
+    let mappings = mappings(extracted, &selection)?;
+    Ok(Some((mappings, selection)))
+}
+
+/// Get the mappings for the selected sub-packages
+///
+/// Files are mapped one by one so that skipped plugins and package.txt are left out. Later
+/// sub-packages overwrite earlier ones.
+fn mappings(extracted: &Path, selection: &Selection) -> crate::Result<Vec<FileMapping>> {
+    let mut skipped: HashSet<String> = selection
+        .deselected_espms
+        .iter()
+        .map(|espm| espm.to_lowercase())
+        .collect();
+    skipped.insert("package.txt".into());
+    let mut mappings = Vec::new();
+    for (priority, name) in selection.sub_packages.iter().enumerate() {
+        let folder = extracted.join(name);
+        for entry in WalkDir::new(&folder).min_depth(1) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let file_name = entry.file_name().to_string_lossy().to_lowercase();
+            if entry.depth() == 1 && skipped.contains(&file_name) {
+                continue;
+            }
+            let relative = entry.path().strip_prefix(&folder).unwrap().to_path_buf();
+            mappings.push(FileMapping {
+                source: Path::new(name).join(&relative),
+                destination: relative,
+                folder: false,
+                priority: priority as i32,
+            });
+        }
+    }
+    Ok(mappings)
+}
+
+/// Choose sub-packages from a list
+///
+/// An answers file can choose them with a "Sub-packages" group. Returns `None` if the mod
+/// should be skipped.
+fn select_sub_packages(
+    mod_name: &str,
+    package: &Package,
+    options: &InstallOptions,
+    previous: Option<&Selection>,
+) -> crate::Result<Option<Selection>> {
+    if let Some(names) = options.answer(mod_name, SUB_PACKAGES) {
+        let selected: Option<Vec<usize>> =
+            names.iter().map(|name| package.sub_package(name)).collect();
+        match selected {
+            Some(selected) => {
+                return Ok(Some(Selection {
+                    sub_packages: selected
+                        .into_iter()
+                        .map(|i| package.sub_packages[i].name.clone())
+                        .collect(),
+                    ..Selection::default()
+                }))
+            }
+            None => colorln!(
+                yellow,
+                "Ignoring the answers for {:?} because it does not have all of their sub-packages",
+                mod_name
+            ),
+        }
+    }
+    if let Some(previous) = previous {
+        return Ok(Some(Selection {
+            sub_packages: previous.sub_packages.clone(),
+            deselected_espms: previous.deselected_espms.clone(),
+            ..Selection::default()
+        }));
+    }
+    let defaults = package.default_selection();
+    let selected = match options.mode {
+        InstallMode::Defaults => defaults,
+        InstallMode::Fail => {
+            return Err(crate::Error::InputRequired {
+                mod_name: mod_name.into(),
+                group: SUB_PACKAGES.into(),
+            })
+        }
+        InstallMode::Skip => {
+            colorln!(yellow, "Skipped {:?} because it needs input", mod_name);
+            return Ok(None);
+        }
+        InstallMode::Prompt => {
+            println!("Sub-packages");
+            for (i, sub) in package.sub_packages.iter().enumerate() {
+                let marker = if defaults.contains(&i) { '*' } else { ' ' };
+                println!(" {}{}. {}", marker, i + 1, sub.name);
+                for line in sub
+                    .description
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                {
+                    colorln!(dimmed, "       {}", line.trim());
+                }
+            }
+            utils::prompt_selection(package.sub_packages.len(), &defaults)?
+        }
+    };
+    Ok(Some(Selection {
+        sub_packages: selected
+            .into_iter()
+            .map(|i| package.sub_packages[i].name.clone())
+            .collect(),
+        ..Selection::default()
+    }))
+}
@@ -0,0 +1,1028 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use indexmap::IndexMap;
+
+use crate::{
+    colorln,
+    fomod::{Environment, FileState, InstallMode, InstallOptions},
+    utils,
+};
+
+use super::{Error, Package, Selection};
+
+fn error<S>(line: usize, message: S) -> Error
+where
+    S: Into<String>,
+{
+    Error::Wizard {
+        line,
+        message: message.into(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Int(i64),
+    Str(String),
+}
+
+impl Value {
+    fn truthy(&self) -> bool {
+        match self {
+            Value::Int(i) => *i != 0,
+            Value::Str(s) => !s.is_empty(),
+        }
+    }
+    fn bool(b: bool) -> Self {
+        Value::Int(b as i64)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Str(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Str(String),
+    Int(i64),
+    Ident(String),
+    Sym(&'static str),
+}
+
+/// Symbols, with longer ones first so they are matched before their prefixes
+const SYMBOLS: &[&str] = &[
+    "==", "!=", "<=", ">=", "+=", "-=", "<", ">", "=", "+", "-", "*", "/", "%", "!", "&", "|", "(",
+    ")", ",",
+];
+
+/// Remove a `;` comment from a line
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, ';') => return &line[..i],
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Split a script into lines with their line numbers, joining lines that end in `\`
+fn logical_lines(script: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    for (i, line) in script.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        let (continues, line) = match line.strip_suffix('\\') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let (number, text) = current.get_or_insert_with(|| (i + 1, String::new()));
+        text.push_str(line);
+        text.push(' ');
+        if !continues {
+            if !text.trim().is_empty() {
+                lines.push((*number, text.trim().to_string()));
+            }
+            current = None;
+        }
+    }
+    lines.extend(current);
+    lines
+}
+
+fn tokenize(line: usize, text: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(i, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, 'n')) => s.push('\n'),
+                        Some((_, 't')) => s.push('\t'),
+                        Some((_, c)) => s.push(c),
+                        None => s.push('\\'),
+                    },
+                    Some((_, end)) if end == c => break,
+                    Some((_, c)) => s.push(c),
+                    None => return Err(error(line, "Unterminated string")),
+                }
+            }
+            tokens.push(Token::Str(s));
+        } else if c.is_ascii_digit() {
+            let mut end = i;
+            while let Some(&(j, c)) = chars.peek() {
+                if !c.is_ascii_digit() {
+                    break;
+                }
+                end = j + c.len_utf8();
+                chars.next();
+            }
+            let n = text[i..end]
+                .parse()
+                .map_err(|_| error(line, format!("Invalid number {:?}", &text[i..end])))?;
+            tokens.push(Token::Int(n));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = i;
+            while let Some(&(j, c)) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_') {
+                    break;
+                }
+                end = j + c.len_utf8();
+                chars.next();
+            }
+            tokens.push(Token::Ident(text[i..end].into()));
+        } else if let Some(sym) = SYMBOLS.iter().find(|sym| text[i..].starts_with(**sym)) {
+            for _ in 0..sym.len() {
+                chars.next();
+            }
+            tokens.push(Token::Sym(sym));
+        } else {
+            return Err(error(line, format!("Unexpected character {:?}", c)));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug)]
+enum Expr {
+    Value(Value),
+    Var(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+/// A `Case` or, if it has no label, a `Default` in a select
+#[derive(Debug)]
+struct Case {
+    label: Option<Expr>,
+    body: Vec<Stmt>,
+}
+
+#[derive(Debug)]
+enum StmtKind {
+    Command(String, Vec<Expr>),
+    Assign(String, &'static str, Expr),
+    If(Vec<(Expr, Vec<Stmt>)>, Vec<Stmt>),
+    Select {
+        many: bool,
+        args: Vec<Expr>,
+        cases: Vec<Case>,
+    },
+    Break,
+    Return,
+}
+
+#[derive(Debug)]
+struct Stmt {
+    line: usize,
+    kind: StmtKind,
+}
+
+/// Parses an expression from a line's tokens
+struct ExprParser<'a> {
+    line: usize,
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> ExprParser<'a> {
+    fn new(line: usize, tokens: &'a [Token]) -> Self {
+        ExprParser {
+            line,
+            tokens,
+            pos: 0,
+        }
+    }
+    fn done(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+    /// Consume the next token if it is one of the given operators
+    fn operator(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        let op = match self.tokens.get(self.pos)? {
+            Token::Sym(sym) => ops.iter().find(|op| *op == sym)?,
+            Token::Ident(word) => match word.as_str() {
+                "and" => ops.iter().find(|op| **op == "&")?,
+                "or" => ops.iter().find(|op| **op == "|")?,
+                "not" => ops.iter().find(|op| **op == "!")?,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        self.pos += 1;
+        Some(op)
+    }
+    fn binary<F>(&mut self, ops: &[&'static str], next: F) -> Result<Expr, Error>
+    where
+        F: Fn(&mut Self) -> Result<Expr, Error>,
+    {
+        let mut expr = next(self)?;
+        while let Some(op) = self.operator(ops) {
+            expr = Expr::Binary(op, Box::new(expr), Box::new(next(self)?));
+        }
+        Ok(expr)
+    }
+    fn expr(&mut self) -> Result<Expr, Error> {
+        self.binary(&["|"], |p| p.binary(&["&"], Self::not))
+    }
+    fn not(&mut self) -> Result<Expr, Error> {
+        if self.operator(&["!"]).is_some() {
+            Ok(Expr::Unary("!", Box::new(self.not()?)))
+        } else {
+            self.binary(&["==", "!=", "<=", ">=", "<", ">"], |p| {
+                p.binary(&["+", "-"], |p| p.binary(&["*", "/", "%"], Self::unary))
+            })
+        }
+    }
+    fn unary(&mut self) -> Result<Expr, Error> {
+        if self.operator(&["-"]).is_some() {
+            Ok(Expr::Unary("-", Box::new(self.unary()?)))
+        } else {
+            self.primary()
+        }
+    }
+    fn primary(&mut self) -> Result<Expr, Error> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .ok_or_else(|| error(self.line, "Expected an expression"))?;
+        self.pos += 1;
+        Ok(match token {
+            Token::Str(s) => Expr::Value(Value::Str(s.clone())),
+            Token::Int(i) => Expr::Value(Value::Int(*i)),
+            Token::Ident(name) if self.operator(&["("]).is_some() => {
+                let mut args = Vec::new();
+                while self.operator(&[")"]).is_none() {
+                    if self.done() {
+                        return Err(error(self.line, "Missing \")\""));
+                    }
+                    args.push(self.expr()?);
+                    self.operator(&[","]);
+                }
+                Expr::Call(name.clone(), args)
+            }
+            Token::Ident(name) if name == "True" => Expr::Value(Value::Int(1)),
+            Token::Ident(name) if name == "False" => Expr::Value(Value::Int(0)),
+            Token::Ident(name) => Expr::Var(name.clone()),
+            Token::Sym("(") => {
+                let expr = self.expr()?;
+                if self.operator(&[")"]).is_none() {
+                    return Err(error(self.line, "Missing \")\""));
+                }
+                expr
+            }
+            Token::Sym(sym) => return Err(error(self.line, format!("Unexpected {:?}", sym))),
+        })
+    }
+    /// Parse an expression that must use all of the tokens
+    fn whole(mut self) -> Result<Expr, Error> {
+        let expr = self.expr()?;
+        if !self.done() {
+            return Err(error(self.line, "Unexpected text after expression"));
+        }
+        Ok(expr)
+    }
+    /// Parse arguments separated by commas or spaces
+    fn args(mut self) -> Result<Vec<Expr>, Error> {
+        let mut args = Vec::new();
+        while !self.done() {
+            args.push(self.expr()?);
+            self.operator(&[","]);
+        }
+        Ok(args)
+    }
+}
+
+/// Parses statements from lines of tokens
+struct Parser {
+    lines: Vec<(usize, Vec<Token>)>,
+    pos: usize,
+}
+
+impl Parser {
+    /// Get the keyword that starts the current line
+    fn keyword(&self) -> Option<&str> {
+        match self.lines.get(self.pos)?.1.first()? {
+            Token::Ident(word) => Some(word),
+            _ => None,
+        }
+    }
+    /// Parse statements until a line starts with one of the `ends` keywords
+    ///
+    /// The last of the `ends` is the one that closes the block, like `EndIf`.
+    fn block(&mut self, ends: &[&str]) -> Result<Vec<Stmt>, Error> {
+        let mut stmts = Vec::new();
+        loop {
+            match self.keyword() {
+                Some(word) if ends.contains(&word) => return Ok(stmts),
+                _ if self.pos >= self.lines.len() => {
+                    return match ends.last() {
+                        Some(end) => {
+                            let line = self.lines.last().map_or(0, |(line, _)| *line);
+                            Err(error(line, format!("Missing {}", end)))
+                        }
+                        None => Ok(stmts),
+                    }
+                }
+                _ => stmts.push(self.stmt()?),
+            }
+        }
+    }
+    fn stmt(&mut self) -> Result<Stmt, Error> {
+        let (line, tokens) = self.lines[self.pos].clone();
+        self.pos += 1;
+        let name = match tokens.first() {
+            Some(Token::Ident(name)) => name.as_str(),
+            _ => return Err(error(line, "Expected a command")),
+        };
+        let rest = &tokens[1..];
+        let kind = match name {
+            "If" => {
+                let mut branches = vec![(ExprParser::new(line, rest).whole()?, Vec::new())];
+                let mut otherwise = Vec::new();
+                loop {
+                    let body = self.block(&["Elif", "Else", "EndIf"])?;
+                    let (end_line, end) = self.lines[self.pos].clone();
+                    self.pos += 1;
+                    match &end[0] {
+                        Token::Ident(word) if word == "Elif" => {
+                            branches.last_mut().unwrap().1 = body;
+                            let cond = ExprParser::new(end_line, &end[1..]).whole()?;
+                            branches.push((cond, Vec::new()));
+                        }
+                        Token::Ident(word) if word == "Else" => {
+                            branches.last_mut().unwrap().1 = body;
+                            otherwise = self.block(&["EndIf"])?;
+                            self.pos += 1;
+                            break;
+                        }
+                        _ => {
+                            branches.last_mut().unwrap().1 = body;
+                            break;
+                        }
+                    }
+                }
+                StmtKind::If(branches, otherwise)
+            }
+            "SelectOne" | "SelectMany" => {
+                let args = ExprParser::new(line, rest).args()?;
+                let mut cases = Vec::new();
+                loop {
+                    let ends = ["Case", "Default", "EndSelect"];
+                    if !self.block(&ends)?.is_empty() {
+                        return Err(error(line, "Commands must be inside a Case or Default"));
+                    }
+                    let (case_line, case) = self.lines[self.pos].clone();
+                    self.pos += 1;
+                    let label = match &case[0] {
+                        Token::Ident(word) if word == "Case" => {
+                            Some(ExprParser::new(case_line, &case[1..]).whole()?)
+                        }
+                        Token::Ident(word) if word == "Default" => None,
+                        _ => break,
+                    };
+                    let body = self.block(&ends)?;
+                    cases.push(Case { label, body });
+                }
+                StmtKind::Select {
+                    many: name == "SelectMany",
+                    args,
+                    cases,
+                }
+            }
+            "While" | "For" | "Continue" => {
+                return Err(error(line, format!("{} is not supported", name)))
+            }
+            "Elif" | "Else" | "EndIf" | "Case" | "Default" | "EndSelect" => {
+                return Err(error(line, format!("Unexpected {}", name)))
+            }
+            "Break" => StmtKind::Break,
+            "Return" => StmtKind::Return,
+            _ => match rest.first() {
+                Some(Token::Sym(op)) if ["=", "+=", "-="].contains(op) => {
+                    StmtKind::Assign(name.into(), op, ExprParser::new(line, &rest[1..]).whole()?)
+                }
+                _ => StmtKind::Command(name.into(), ExprParser::new(line, rest).args()?),
+            },
+        };
+        Ok(Stmt { line, kind })
+    }
+}
+
+/// A parsed wizard.txt script
+#[derive(Debug)]
+pub struct Wizard {
+    body: Vec<Stmt>,
+}
+
+impl Wizard {
+    pub fn parse(script: &str) -> Result<Self, Error> {
+        let lines = logical_lines(script)
+            .into_iter()
+            .map(|(line, text)| Ok((line, tokenize(line, &text)?)))
+            .collect::<Result<_, Error>>()?;
+        let mut parser = Parser { lines, pos: 0 };
+        Ok(Wizard {
+            body: parser.block(&[])?,
+        })
+    }
+    /// Run the wizard to choose the sub-packages and plugins to install
+    ///
+    /// Prompts answered in `previous` are not asked again. Returns `None` if the wizard
+    /// was cancelled or the mod was skipped.
+    pub fn run(
+        &self,
+        mod_name: &str,
+        package: &Package,
+        env: &Environment,
+        options: &InstallOptions,
+        previous: Option<&IndexMap<String, Vec<String>>>,
+    ) -> crate::Result<Option<Selection>> {
+        let mut run = Run {
+            mod_name,
+            package,
+            env,
+            options,
+            previous,
+            answers: IndexMap::new(),
+            vars: HashMap::new(),
+            selected: vec![false; package.sub_packages.len()],
+            deselected: HashSet::new(),
+            notes: Vec::new(),
+        };
+        if run.exec(&self.body)? == Flow::Cancel {
+            return Ok(None);
+        }
+        for note in &run.notes {
+            colorln!(dimmed, "Note: {}", note);
+        }
+        let mut deselected_espms = Vec::new();
+        for (sub, _) in package
+            .sub_packages
+            .iter()
+            .zip(&run.selected)
+            .filter(|(_, selected)| **selected)
+        {
+            for espm in &sub.espms {
+                if run.deselected.contains(&espm.to_lowercase()) && !deselected_espms.contains(espm)
+                {
+                    deselected_espms.push(espm.clone());
+                }
+            }
+        }
+        Ok(Some(Selection {
+            sub_packages: package
+                .sub_packages
+                .iter()
+                .zip(&run.selected)
+                .filter(|(_, selected)| **selected)
+                .map(|(sub, _)| sub.name.clone())
+                .collect(),
+            deselected_espms,
+            wizard: run.answers,
+            ..Selection::default()
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flow {
+    Next,
+    Break,
+    Return,
+    Cancel,
+}
+
+/// The state of a running wizard
+struct Run<'a> {
+    mod_name: &'a str,
+    package: &'a Package,
+    env: &'a Environment,
+    options: &'a InstallOptions,
+    previous: Option<&'a IndexMap<String, Vec<String>>>,
+    answers: IndexMap<String, Vec<String>>,
+    vars: HashMap<String, Value>,
+    selected: Vec<bool>,
+    /// Lowercase names of deselected plugins
+    deselected: HashSet<String>,
+    notes: Vec<String>,
+}
+
+impl<'a> Run<'a> {
+    fn exec(&mut self, stmts: &[Stmt]) -> crate::Result<Flow> {
+        for stmt in stmts {
+            let flow = match &stmt.kind {
+                StmtKind::Command(name, args) => self.command(stmt.line, name, args)?,
+                StmtKind::Assign(var, op, expr) => {
+                    let mut value = self.eval(stmt.line, expr)?;
+                    if *op != "=" {
+                        let old = self.var(stmt.line, var)?;
+                        value = binary(stmt.line, &op[..1], old, value)?;
+                    }
+                    self.vars.insert(var.clone(), value);
+                    Flow::Next
+                }
+                StmtKind::If(branches, otherwise) => {
+                    let mut body = otherwise;
+                    for (cond, branch) in branches {
+                        if self.eval(stmt.line, cond)?.truthy() {
+                            body = branch;
+                            break;
+                        }
+                    }
+                    self.exec(body)?
+                }
+                StmtKind::Select { many, args, cases } => {
+                    self.select(stmt.line, *many, args, cases)?
+                }
+                StmtKind::Break => Flow::Break,
+                StmtKind::Return => Flow::Return,
+            };
+            if flow != Flow::Next {
+                return Ok(flow);
+            }
+        }
+        Ok(Flow::Next)
+    }
+    fn strings(&self, line: usize, args: &[Expr]) -> Result<Vec<String>, Error> {
+        args.iter()
+            .map(|arg| self.eval(line, arg).map(|value| value.to_string()))
+            .collect()
+    }
+    fn command(&mut self, line: usize, name: &str, args: &[Expr]) -> crate::Result<Flow> {
+        let args = self.strings(line, args)?;
+        let arg = || {
+            args.first()
+                .ok_or_else(|| error(line, format!("{} needs an argument", name)))
+        };
+        match name {
+            "SelectSubPackage" | "DeSelectSubPackage" => {
+                let sub = arg()?;
+                if let Some(i) = self.package.sub_package(sub) {
+                    let select = name == "SelectSubPackage";
+                    self.selected[i] = select;
+                    // Selecting a sub-package selects its plugins, like checking it in Wrye Bash
+                    if select {
+                        for espm in &self.package.sub_packages[i].espms {
+                            self.deselected.remove(&espm.to_lowercase());
+                        }
+                    }
+                } else {
+                    colorln!(yellow, "Unknown sub-package {:?}", sub);
+                }
+            }
+            "SelectEspm" => {
+                self.deselected.remove(&arg()?.to_lowercase());
+            }
+            "DeSelectEspm" => {
+                self.deselected.insert(arg()?.to_lowercase());
+            }
+            "SelectAll" | "DeSelectAll" => {
+                let select = name == "SelectAll";
+                for selected in &mut self.selected {
+                    *selected = select;
+                }
+                self.set_all_espms(select);
+            }
+            "SelectAllEspms" => self.set_all_espms(true),
+            "DeSelectAllEspms" => self.set_all_espms(false),
+            // The versions of the game and script extenders are unknown, so they are only shown
+            "RequireVersions" => {
+                let required: Vec<_> =
+                    ["game", "script extender", "graphics extender", "Wrye Bash"]
+                        .iter()
+                        .zip(&args)
+                        .filter(|(_, version)| !version.is_empty())
+                        .map(|(program, version)| format!("{} {}", program, version))
+                        .collect();
+                if !required.is_empty() {
+                    colorln!(
+                        yellow,
+                        "{:?} requires {}, which could not be checked",
+                        self.mod_name,
+                        required.join(", ")
+                    );
+                }
+            }
+            "Note" => self.notes.push(arg()?.clone()),
+            "Cancel" => {
+                let reason = args.first().map(String::as_str).unwrap_or_default();
+                colorln!(
+                    yellow,
+                    "The wizard for {:?} was cancelled. {}",
+                    self.mod_name,
+                    reason
+                );
+                return Ok(Flow::Cancel);
+            }
+            "EditINI" | "DisableINILine" | "RenameEspm" | "ResetEspmName" | "ResetAllEspmNames" => {
+                colorln!(yellow, "{} is not supported and was ignored", name);
+            }
+            _ => return Err(error(line, format!("Unknown command {:?}", name)).into()),
+        }
+        Ok(Flow::Next)
+    }
+    fn set_all_espms(&mut self, select: bool) {
+        if select {
+            self.deselected.clear();
+        } else {
+            self.deselected.extend(
+                self.package
+                    .sub_packages
+                    .iter()
+                    .flat_map(|sub| &sub.espms)
+                    .map(|espm| espm.to_lowercase()),
+            );
+        }
+    }
+    fn select(
+        &mut self,
+        line: usize,
+        many: bool,
+        args: &[Expr],
+        cases: &[Case],
+    ) -> crate::Result<Flow> {
+        let args = self.strings(line, args)?;
+        let (prompt, rest) = args
+            .split_first()
+            .ok_or_else(|| error(line, "Select needs a prompt"))?;
+        if rest.is_empty() || rest.len() % 3 != 0 {
+            return Err(error(line, "Options need a name, description and image").into());
+        }
+        // Default options start with |
+        let options: Vec<(&str, &str, bool)> = rest
+            .chunks(3)
+            .map(|option| match option[0].strip_prefix('|') {
+                Some(name) => (name, option[1].as_str(), true),
+                None => (option[0].as_str(), option[1].as_str(), false),
+            })
+            .collect();
+        let chosen = if let Some(chosen) = self.choose(prompt, &options, many)? {
+            chosen
+        } else {
+            colorln!(yellow, "Skipped {:?} because it needs input", self.mod_name);
+            return Ok(Flow::Cancel);
+        };
+        let names: Vec<String> = chosen.iter().map(|&i| options[i].0.to_string()).collect();
+        self.answers.insert(prompt.clone(), names.clone());
+        let mut matched = false;
+        for case in cases {
+            let label = match &case.label {
+                Some(label) => self.eval(line, label)?.to_string(),
+                None => continue,
+            };
+            if names.contains(&label) {
+                matched = true;
+                match self.exec(&case.body)? {
+                    Flow::Next | Flow::Break => {}
+                    flow => return Ok(flow),
+                }
+            }
+        }
+        if !matched {
+            if let Some(default) = cases.iter().find(|case| case.label.is_none()) {
+                match self.exec(&default.body)? {
+                    Flow::Next | Flow::Break => {}
+                    flow => return Ok(flow),
+                }
+            }
+        }
+        Ok(Flow::Next)
+    }
+    /// Choose options for a select
+    ///
+    /// Returns `None` if the mod should be skipped.
+    fn choose(
+        &self,
+        prompt: &str,
+        options: &[(&str, &str, bool)],
+        many: bool,
+    ) -> crate::Result<Option<Vec<usize>>> {
+        let print_selection = |selected: &[usize]| {
+            let names: Vec<&str> = selected.iter().map(|&i| options[i].0).collect();
+            if names.is_empty() {
+                println!("{}: none", prompt);
+            } else {
+                println!("{}: {}", prompt, names.join(", "));
+            }
+        };
+        // Use an answer from an answers file, or else a previous answer, if all of its
+        // options still exist
+        let answers = self.options.answer(self.mod_name, prompt);
+        let previous = self.previous.and_then(|previous| previous.get(prompt));
+        for names in answers.into_iter().chain(previous) {
+            let selected: Option<Vec<usize>> = names
+                .iter()
+                .map(|name| options.iter().position(|option| option.0 == name))
+                .collect();
+            if let Some(selected) = selected.filter(|selected| many || selected.len() == 1) {
+                print_selection(&selected);
+                return Ok(Some(selected));
+            }
+        }
+        let mut defaults: Vec<usize> = (0..options.len()).filter(|&i| options[i].2).collect();
+        if !many {
+            defaults.truncate(1);
+            if defaults.is_empty() {
+                defaults.push(0);
+            }
+        }
+        let forced = !many && options.len() == 1;
+        match self.options.mode {
+            _ if forced => {}
+            InstallMode::Prompt => {}
+            InstallMode::Defaults => {}
+            InstallMode::Fail => {
+                return Err(crate::Error::InputRequired {
+                    mod_name: self.mod_name.into(),
+                    group: prompt.into(),
+                })
+            }
+            InstallMode::Skip => return Ok(None),
+        }
+        if forced || self.options.mode == InstallMode::Defaults {
+            print_selection(&defaults);
+            return Ok(Some(defaults));
+        }
+        let kind = if many {
+            "select any"
+        } else {
+            "select exactly one"
+        };
+        println!("{} ({})", prompt, kind);
+        for (i, (name, description, _)) in options.iter().enumerate() {
+            let marker = if defaults.contains(&i) { '*' } else { ' ' };
+            println!(" {}{}. {}", marker, i + 1, name);
+            for line in description.lines().filter(|line| !line.trim().is_empty()) {
+                colorln!(dimmed, "       {}", line.trim());
+            }
+        }
+        loop {
+            let selected = utils::prompt_selection(options.len(), &defaults)?;
+            if many || selected.len() == 1 {
+                return Ok(Some(selected));
+            }
+            colorln!(yellow, "Please select exactly one option");
+        }
+    }
+    fn var(&self, line: usize, name: &str) -> Result<Value, Error> {
+        self.vars
+            .get(name)
+            .cloned()
+            .ok_or_else(|| error(line, format!("Unknown variable {:?}", name)))
+    }
+    fn eval(&self, line: usize, expr: &Expr) -> Result<Value, Error> {
+        Ok(match expr {
+            Expr::Value(value) => value.clone(),
+            Expr::Var(name) => self.var(line, name)?,
+            Expr::Unary(op, expr) => {
+                let value = self.eval(line, expr)?;
+                match (*op, value) {
+                    ("-", Value::Int(i)) => Value::Int(
+                        i.checked_neg()
+                            .ok_or_else(|| error(line, "Integer overflow"))?,
+                    ),
+                    ("-", value) => return Err(error(line, format!("Cannot negate {:?}", value))),
+                    (_, value) => Value::bool(!value.truthy()),
+                }
+            }
+            Expr::Binary(op, a, b) => binary(line, op, self.eval(line, a)?, self.eval(line, b)?)?,
+            Expr::Call(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval(line, arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.call(line, name, args)?
+            }
+        })
+    }
+    fn call(&self, line: usize, name: &str, args: Vec<Value>) -> Result<Value, Error> {
+        let first = || {
+            args.first()
+                .ok_or_else(|| error(line, format!("{} needs an argument", name)))
+        };
+        Ok(match name {
+            "DataFileExists" => Value::bool(
+                args.iter()
+                    .all(|file| self.env.file_state(&file.to_string()) != FileState::Missing),
+            ),
+            "GetEspmStatus" => Value::Int(match self.env.file_state(&first()?.to_string()) {
+                FileState::Missing => -1,
+                FileState::Inactive => 0,
+                FileState::Active => 1,
+            }),
+            // The versions of the game and script extenders are unknown, so assume they match
+            _ if name.starts_with("Compare") && name.ends_with("Version") => Value::Int(0),
+            "str" => Value::Str(first()?.to_string()),
+            "int" => match first()? {
+                Value::Int(i) => Value::Int(*i),
+                Value::Str(s) => Value::Int(s.trim().parse().unwrap_or(0)),
+            },
+            "len" => Value::Int(first()?.to_string().chars().count() as i64),
+            _ => return Err(error(line, format!("Unknown function {:?}", name))),
+        })
+    }
+}
+
+fn binary(line: usize, op: &str, a: Value, b: Value) -> Result<Value, Error> {
+    let ordering = || match (&a, &b) {
+        (Value::Int(a), Value::Int(b)) => a.cmp(b),
+        _ => a.to_string().cmp(&b.to_string()),
+    };
+    Ok(match op {
+        "+" => match (&a, &b) {
+            (Value::Int(a), Value::Int(b)) => Value::Int(
+                a.checked_add(*b)
+                    .ok_or_else(|| error(line, "Integer overflow"))?,
+            ),
+            _ => Value::Str(format!("{}{}", a, b)),
+        },
+        "-" | "*" | "/" | "%" => {
+            let (a, b) = match (&a, &b) {
+                (Value::Int(a), Value::Int(b)) => (*a, *b),
+                _ => {
+                    return Err(error(
+                        line,
+                        format!("{:?} {} {:?} is not a number", a, op, b),
+                    ))
+                }
+            };
+            if b == 0 && (op == "/" || op == "%") {
+                return Err(error(line, "Division by zero"));
+            }
+            let result = match op {
+                "-" => a.checked_sub(b),
+                "*" => a.checked_mul(b),
+                "/" => a.checked_div(b),
+                _ => a.checked_rem(b),
+            };
+            Value::Int(result.ok_or_else(|| error(line, "Integer overflow"))?)
+        }
+        "&" => Value::bool(a.truthy() && b.truthy()),
+        "|" => Value::bool(a.truthy() || b.truthy()),
+        "==" => Value::bool(ordering() == Ordering::Equal),
+        "!=" => Value::bool(ordering() != Ordering::Equal),
+        "<" => Value::bool(ordering() == Ordering::Less),
+        ">" => Value::bool(ordering() == Ordering::Greater),
+        "<=" => Value::bool(ordering() != Ordering::Greater),
+        _ => Value::bool(ordering() != Ordering::Less),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bain::SubPackage;
+
+    fn package() -> Package {
+        Package {
+            description: String::new(),
+            sub_packages: ["00 Core", "01 A", "02 B"]
+                .iter()
+                .map(|name| SubPackage {
+                    name: name.to_string(),
+                    description: String::new(),
+                    espms: Vec::new(),
+                })
+                .collect(),
+            wizard: None,
+        }
+    }
+
+    /// Run a script with default answers, returning the selected sub-packages
+    fn run(script: &str) -> crate::Result<Vec<String>> {
+        let options = InstallOptions {
+            mode: InstallMode::Defaults,
+            ..InstallOptions::default()
+        };
+        let selection = Wizard::parse(script)?
+            .run("Test", &package(), &Environment::default(), &options, None)?
+            .unwrap();
+        Ok(selection.sub_packages)
+    }
+
+    /// Get the line and message of a script's error
+    fn run_error(script: &str) -> (usize, String) {
+        match run(script) {
+            Err(crate::Error::Bain(Error::Wizard { line, message })) => (line, message),
+            result => panic!("Expected a wizard error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn if_elif_else() {
+        let script = |x| {
+            format!(
+                "x = {}\n\
+                If x == 1\n\
+                    SelectSubPackage \"01 A\"\n\
+                Elif x == 2\n\
+                    SelectSubPackage \"02 B\"\n\
+                Else\n\
+                    SelectSubPackage \"00 Core\"\n\
+                EndIf",
+                x
+            )
+        };
+        assert_eq!(run(&script(1)).unwrap(), ["01 A"]);
+        assert_eq!(run(&script(2)).unwrap(), ["02 B"]);
+        assert_eq!(run(&script(3)).unwrap(), ["00 Core"]);
+    }
+
+    #[test]
+    fn select_defaults() {
+        let script = "SelectOne \"Pick\", \\\n\
+                \"A\", \"\", \"\", \\\n\
+                \"|B\", \"\", \"\"\n\
+            Case \"A\"\n\
+                SelectSubPackage \"01 A\"\n\
+                Break\n\
+            Case \"B\"\n\
+                SelectSubPackage \"02 B\"\n\
+                Break\n\
+            EndSelect\n\
+            SelectMany \"Extras\", \"|Core\", \"\", \"\", \"A\", \"\", \"\"\n\
+            Case \"Core\"\n\
+                SelectSubPackage \"00 Core\"\n\
+            Case \"A\"\n\
+                SelectSubPackage \"01 A\"\n\
+            EndSelect";
+        assert_eq!(run(script).unwrap(), ["00 Core", "02 B"]);
+    }
+
+    #[test]
+    fn missing_terminators() {
+        assert_eq!(
+            run_error("If 1\n    Note \"x\""),
+            (2, "Missing EndIf".into())
+        );
+        assert_eq!(
+            run_error("If 1\nElse\n    Note \"x\""),
+            (3, "Missing EndIf".into())
+        );
+        assert_eq!(
+            run_error("SelectOne \"Pick\", \"A\", \"\", \"\"\n    Case \"A\""),
+            (2, "Missing EndSelect".into())
+        );
+    }
+
+    #[test]
+    fn error_lines() {
+        assert_eq!(run_error("Note \"x\"\n\nElse").0, 3);
+        // Continued lines report the line they start on
+        assert_eq!(run_error("; comment\nx = 1 - \\\n    \"a\"").0, 2);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let value = |expr: &str| {
+            run(&format!(
+                "x = {}\nIf x == 3\n    SelectSubPackage \"00 Core\"\nEndIf",
+                expr
+            ))
+            .map(|selected| !selected.is_empty())
+        };
+        assert!(value("1 + 2").unwrap());
+        assert!(value("7 - 2 * 2").unwrap());
+        assert!(value("10 / 3").unwrap());
+        assert!(value("-3 % 5 + 6").unwrap());
+        assert!(value("-(-3)").unwrap());
+    }
+
+    #[test]
+    fn overflow() {
+        let max = i64::MAX;
+        let overflowing = [
+            format!("x = {} + 1", max),
+            format!("x = -{} - 2", max),
+            format!("x = {} * 2", max),
+            format!("x = -{} - 1\ny = -x", max),
+            format!("x = -{} - 1\ny = x / -1", max),
+            format!("x = -{} - 1\ny = x % -1", max),
+            format!("x = {}\nx += 1", max),
+        ];
+        for script in &overflowing {
+            assert_eq!(run_error(script).1, "Integer overflow", "{}", script);
+        }
+        assert_eq!(run_error("x = 1 / 0").1, "Division by zero");
+        assert_eq!(run_error("x = 1 % 0").1, "Division by zero");
+    }
+}
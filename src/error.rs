@@ -8,6 +8,8 @@ pub enum Error {
     Serialize(#[from] toml::ser::Error),
     #[error("Toml error: {0}")]
     Deserialize(#[from] toml::de::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
     #[error("No home directory")]
     NoHomeDirectory,
     #[error("No user downloads folder")]
@@ -22,6 +24,8 @@ pub enum Error {
     WalkDir(#[from] walkdir::Error),
     #[error("Fomod error: {0}")]
     Fomod(#[from] crate::fomod::Error),
+    #[error("BAIN error: {0}")]
+    Bain(#[from] crate::bain::Error),
     #[error("No mod found for {0:?}")]
     UnknownMod(String),
     #[error("Notify error: {0}")]
@@ -32,12 +36,31 @@ pub enum Error {
     NoGameExectuable,
     #[error("Error extracting {archive:?} (error code {code:?})")]
     Extraction { archive: PathBuf, code: Option<i32> },
+    #[error("Error reading {archive:?}: {message}")]
+    Archive { archive: PathBuf, message: String },
+    #[error("Error extracting {entry:?} from {archive:?}: {message}")]
+    ArchiveEntry {
+        archive: PathBuf,
+        entry: String,
+        message: String,
+    },
+    #[error("Unable to extract {0:?}. Install 7-Zip to extract this kind of archive")]
+    NoExtractor(PathBuf),
     #[error("Unknown profile {0:?}")]
     UnknownProfile(String),
     #[error("No profile loaded")]
     NoProfileLoaded,
     #[error("Profile exists {0:?}")]
     ProfileExists(String),
+    #[error("{mod_name:?} needs an answer for {group:?}")]
+    InputRequired { mod_name: String, group: String },
+    #[error("Unable to deploy {} files:\n  {}", .0.len(), .0.join("\n  "))]
+    DeployFailed(Vec<String>),
+    #[error("{mod_name:?} has unmet requirements: {}", unmet.join(", "))]
+    UnmetDependencies {
+        mod_name: String,
+        unmet: Vec<String>,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
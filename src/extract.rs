@@ -0,0 +1,308 @@
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind, Read},
+    path::{Path, PathBuf},
+    process::Command,
+    time::UNIX_EPOCH,
+};
+
+use serde_derive::{Deserialize, Serialize};
+use sevenz_rust::{Password, SevenZReader};
+use sha2::{Digest, Sha256};
+use zip::ZipArchive;
+
+use walkdir::WalkDir;
+
+use crate::progress::Status;
+
+/// Called after each entry is extracted
+pub type Report<'a> = &'a mut dyn FnMut(Status);
+
+/// A way of extracting archives
+pub trait Extractor {
+    /// Check if the extractor can handle an archive
+    fn supports(&self, archive: &Path) -> bool;
+    fn extract(&self, archive: &Path, dest: &Path, report: Report) -> crate::Result<()>;
+}
+
+/// Check if a file starts with one of the given signatures
+///
+/// Archives are recognized by their contents since downloads are not always named correctly.
+fn has_signature(archive: &Path, signatures: &[&[u8]]) -> bool {
+    let mut start = [0; 6];
+    let read = File::open(archive).and_then(|mut file| file.read(&mut start));
+    read.is_ok_and(|len| {
+        signatures
+            .iter()
+            .any(|signature| start[..len].starts_with(signature))
+    })
+}
+
+/// Get the path of an archive entry, which may use backslashes
+///
+/// Returns `None` if the entry would be extracted outside of the destination.
+fn entry_path(name: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for part in name.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => return None,
+            _ if part.contains(':') => return None,
+            _ => path.push(part),
+        }
+    }
+    Some(path)
+}
+
+fn entry_error<E>(archive: &Path, entry: &str, error: E) -> crate::Error
+where
+    E: ToString,
+{
+    crate::Error::ArchiveEntry {
+        archive: archive.to_path_buf(),
+        entry: entry.into(),
+        message: error.to_string(),
+    }
+}
+
+fn archive_error<E>(archive: &Path, error: E) -> crate::Error
+where
+    E: ToString,
+{
+    crate::Error::Archive {
+        archive: archive.to_path_buf(),
+        message: error.to_string(),
+    }
+}
+
+/// Write an entry to the destination folder, returning the number of bytes written
+fn write_entry<R>(archive: &Path, dest: &Path, name: &str, reader: &mut R) -> crate::Result<u64>
+where
+    R: io::Read + ?Sized,
+{
+    let path = entry_path(name)
+        .map(|path| dest.join(path))
+        .ok_or_else(|| entry_error(archive, name, "The path leaves the archive"))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(&path).map_err(|e| entry_error(archive, name, e))?;
+    let bytes = io::copy(reader, &mut file).map_err(|e| entry_error(archive, name, e))?;
+    Ok(bytes)
+}
+
+fn create_entry_dir(archive: &Path, dest: &Path, name: &str) -> crate::Result<()> {
+    let path = entry_path(name)
+        .ok_or_else(|| entry_error(archive, name, "The path leaves the archive"))?;
+    fs::create_dir_all(dest.join(path))?;
+    Ok(())
+}
+
+/// Extracts zip archives
+pub struct ZipExtractor;
+
+impl Extractor for ZipExtractor {
+    fn supports(&self, archive: &Path) -> bool {
+        has_signature(archive, &[b"PK\x03\x04", b"PK\x05\x06"])
+    }
+    fn extract(&self, archive: &Path, dest: &Path, report: Report) -> crate::Result<()> {
+        let file = File::open(archive)?;
+        let mut zip = ZipArchive::new(file).map_err(|e| archive_error(archive, e))?;
+        let mut status = Status {
+            total_files: zip.len(),
+            ..Status::default()
+        };
+        for i in 0..status.total_files {
+            let mut entry = zip
+                .by_index(i)
+                .map_err(|e| entry_error(archive, &format!("#{}", i + 1), e))?;
+            let name = entry.name().to_string();
+            if entry.is_dir() {
+                create_entry_dir(archive, dest, &name)?;
+            } else {
+                status.bytes += write_entry(archive, dest, &name, &mut entry)?;
+            }
+            status.files += 1;
+            report(status);
+        }
+        Ok(())
+    }
+}
+
+/// Extracts 7z archives
+pub struct SevenZipExtractor;
+
+impl Extractor for SevenZipExtractor {
+    fn supports(&self, archive: &Path) -> bool {
+        has_signature(archive, &[b"7z\xBC\xAF\x27\x1C"])
+    }
+    fn extract(&self, archive: &Path, dest: &Path, report: Report) -> crate::Result<()> {
+        let mut reader = SevenZReader::open(archive, Password::empty())
+            .map_err(|e| archive_error(archive, e))?;
+        let mut status = Status {
+            total_files: reader.archive().files.len(),
+            ..Status::default()
+        };
+        let mut current = String::new();
+        let mut failure = None;
+        let result = reader.for_each_entries(|entry, data| {
+            current = entry.name().to_string();
+            let result = if entry.is_directory() {
+                create_entry_dir(archive, dest, &current).map(|_| 0)
+            } else {
+                write_entry(archive, dest, &current, data)
+            };
+            match result {
+                Ok(bytes) => {
+                    status.files += 1;
+                    status.bytes += bytes;
+                    report(status);
+                    Ok(true)
+                }
+                Err(e) => {
+                    failure = Some(e);
+                    Ok(false)
+                }
+            }
+        });
+        if let Some(e) = failure {
+            return Err(e);
+        }
+        // Decoding errors happen while reading the entry that was last started
+        result.map_err(|e| entry_error(archive, &current, e))
+    }
+}
+
+/// Extracts archives with an installed 7z program, which handles formats like rar that
+/// have no built-in extractor
+pub struct ExternalExtractor;
+
+impl Extractor for ExternalExtractor {
+    fn supports(&self, _archive: &Path) -> bool {
+        true
+    }
+    fn extract(&self, archive: &Path, dest: &Path, report: Report) -> crate::Result<()> {
+        let output = Command::new("7z")
+            .arg("x")
+            .arg(archive)
+            .arg(format!("-o{}", dest.to_string_lossy()))
+            .arg("-spe")
+            .output();
+        let status = match output {
+            Ok(output) => output.status,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Err(crate::Error::NoExtractor(archive.to_path_buf()))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if status.success() {
+            // The program does not report progress, so count what it extracted
+            let mut status = Status::default();
+            for entry in WalkDir::new(dest).into_iter().filter_map(Result::ok) {
+                status.files += 1;
+                status.bytes += entry.metadata().map_or(0, |meta| meta.len());
+            }
+            status.total_files = status.files;
+            report(status);
+            Ok(())
+        } else {
+            Err(crate::Error::Extraction {
+                archive: archive.to_path_buf(),
+                code: status.code(),
+            })
+        }
+    }
+}
+
+/// Get the built-in extractors
+fn builtin() -> Vec<Box<dyn Extractor>> {
+    vec![Box::new(ZipExtractor), Box::new(SevenZipExtractor)]
+}
+
+/// Extract an archive into a folder
+///
+/// A built-in extractor is used if one supports the archive. The 7z program is used for
+/// other archives and for ones a built-in extractor fails on, if it is installed.
+pub fn extract_archive(archive: &Path, dest: &Path, report: Report) -> crate::Result<()> {
+    let builtin_error = match builtin()
+        .into_iter()
+        .find(|extractor| extractor.supports(archive))
+    {
+        Some(extractor) => match extractor.extract(archive, dest, report) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        },
+        None => return ExternalExtractor.extract(archive, dest, report),
+    };
+    let _ = fs::remove_dir_all(dest);
+    match ExternalExtractor.extract(archive, dest, report) {
+        Err(crate::Error::NoExtractor(_)) => Err(builtin_error),
+        result => result,
+    }
+}
+
+/// The size, modification time and hash of an archive when it was extracted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveStamp {
+    pub size: u64,
+    /// Seconds since the Unix epoch
+    pub modified: u64,
+    /// The SHA-256 hash of the archive
+    pub hash: String,
+}
+
+/// How an archive compares to when it was extracted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveState {
+    Unchanged,
+    Changed,
+    Missing,
+    /// The mod was extracted before archives were stamped
+    Unknown,
+}
+
+fn modified(meta: &fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_secs())
+}
+
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+impl ArchiveStamp {
+    pub fn read(archive: &Path) -> io::Result<Self> {
+        let meta = fs::metadata(archive)?;
+        Ok(ArchiveStamp {
+            size: meta.len(),
+            modified: modified(&meta),
+            hash: hash_file(archive)?,
+        })
+    }
+    /// Compare an archive to this stamp
+    ///
+    /// The archive is only hashed if its size or modification time changed.
+    pub fn check(&self, archive: &Path) -> io::Result<ArchiveState> {
+        let meta = match fs::metadata(archive) {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(ArchiveState::Missing),
+            Err(e) => return Err(e),
+        };
+        if meta.len() == self.size && modified(&meta) == self.modified {
+            return Ok(ArchiveState::Unchanged);
+        }
+        if meta.len() == self.size && hash_file(archive)? == self.hash {
+            Ok(ArchiveState::Unchanged)
+        } else {
+            Ok(ArchiveState::Changed)
+        }
+    }
+}
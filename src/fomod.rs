@@ -1,24 +1,36 @@
-#![allow(dead_code, unused_variables)]
+mod check;
+mod condition;
+mod info;
+mod install;
+mod paths;
+pub use {check::*, condition::*, info::*, install::*, paths::*};
 
 use std::{
     borrow::Cow,
     fs,
-    io::{stdin, stdout, BufRead, Read, Write},
+    io::Read,
     path::{Path, PathBuf},
 };
 
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use pathdiff::diff_paths;
 use serde_derive::Deserialize;
 use xmltree::Element;
 
+use crate::{game::FileMapping, layout::RootRules, utils};
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("XML error: {0}")]
-    XML(#[from] xmltree::ParseError),
+    Xml(#[from] xmltree::ParseError),
     #[error("Missing element {0:?}")]
     Missing(String),
+    #[error("Missing attribute {attr:?} on element {elem:?}")]
+    MissingAttribute { elem: String, attr: String },
 }
 
-#[derive(Debug, Clone, Copy, Deserialize)]
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
 pub enum GroupType {
     SelectAny,
     SelectAll,
@@ -27,11 +39,219 @@ pub enum GroupType {
     SelectAtLeastOne,
 }
 
+impl std::str::FromStr for GroupType {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "SelectAny" => GroupType::SelectAny,
+            "SelectAll" => GroupType::SelectAll,
+            "SelectExactlyOne" => GroupType::SelectExactlyOne,
+            "SelectAtMostOne" => GroupType::SelectAtMostOne,
+            "SelectAtLeastOne" => GroupType::SelectAtLeastOne,
+            _ => return Err(s.into()),
+        })
+    }
+}
+
+impl GroupType {
+    /// Check if a number of selected plugins is valid for this group type
+    pub fn allows(self, selected: usize, available: usize) -> bool {
+        match self {
+            GroupType::SelectAny => true,
+            GroupType::SelectAll => selected == available,
+            GroupType::SelectExactlyOne => selected == 1,
+            GroupType::SelectAtMostOne => selected <= 1,
+            GroupType::SelectAtLeastOne => selected >= 1,
+        }
+    }
+    pub fn description(self) -> &'static str {
+        match self {
+            GroupType::SelectAny => "select any",
+            GroupType::SelectAll => "all are installed",
+            GroupType::SelectExactlyOne => "select exactly one",
+            GroupType::SelectAtMostOne => "select at most one",
+            GroupType::SelectAtLeastOne => "select at least one",
+        }
+    }
+}
+
+/// How the steps, groups or plugins in a list are ordered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Order {
+    #[default]
+    Ascending,
+    Descending,
+    Explicit,
+}
+
+impl std::str::FromStr for Order {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "Ascending" => Order::Ascending,
+            "Descending" => Order::Descending,
+            "Explicit" => Order::Explicit,
+            _ => return Err(s.into()),
+        })
+    }
+}
+
+impl Order {
+    /// Get the order of a list element's children
+    fn of(elem: &Element) -> Self {
+        elem.attributes
+            .get("order")
+            .and_then(|order| order.parse().ok())
+            .unwrap_or_default()
+    }
+    /// Sort items by name according to this order
+    ///
+    /// Explicit order keeps the order of the document.
+    fn sort<T, F>(self, items: &mut [T], name: F)
+    where
+        F: Fn(&T) -> &str,
+    {
+        let cmp = |a: &T, b: &T| {
+            let (a, b) = (name(a), name(b));
+            a.to_lowercase()
+                .cmp(&b.to_lowercase())
+                .then_with(|| a.cmp(b))
+        };
+        match self {
+            Order::Ascending => items.sort_by(cmp),
+            Order::Descending => items.sort_by(|a, b| cmp(b, a)),
+            Order::Explicit => {}
+        }
+    }
+}
+
+/// A file or folder entry in a Fomod file list
+#[derive(Debug, Clone)]
+pub struct FileInstall {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    pub folder: bool,
+    pub priority: i32,
+    pub always_install: bool,
+    pub install_if_usable: bool,
+}
+
+impl FileInstall {
+    fn parse(elem: &Element) -> Result<Self, Error> {
+        let source = attr(elem, "source")?;
+        // The destination defaults to the same path as the source
+        let destination = elem
+            .attributes
+            .get("destination")
+            .map(String::as_str)
+            .unwrap_or(source);
+        let flag = |name: &str| elem.attributes.get(name).is_some_and(|val| val == "true");
+        Ok(FileInstall {
+            source: normalize(source),
+            destination: normalize(destination),
+            folder: elem.name == "folder",
+            priority: elem
+                .attributes
+                .get("priority")
+                .and_then(|priority| priority.parse().ok())
+                .unwrap_or(0),
+            always_install: flag("alwaysInstall"),
+            install_if_usable: flag("installIfUsable"),
+        })
+    }
+    /// Get the mapping for this entry, or `None` if its source does not exist
+    pub fn mapping(&self, resolver: &Resolver) -> Option<FileMapping> {
+        Some(FileMapping {
+            source: resolver.resolve(&self.source)?,
+            destination: self.destination.clone(),
+            folder: self.folder,
+            priority: self.priority,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PluginType {
+    Required,
+    Optional,
+    Recommended,
+    NotUsable,
+    CouldBeUsable,
+}
+
+impl std::str::FromStr for PluginType {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "Required" => PluginType::Required,
+            "Optional" => PluginType::Optional,
+            "Recommended" => PluginType::Recommended,
+            "NotUsable" => PluginType::NotUsable,
+            "CouldBeUsable" => PluginType::CouldBeUsable,
+            _ => return Err(s.into()),
+        })
+    }
+}
+
+/// Determines a plugin's type, which may depend on conditions
+#[derive(Debug, Clone)]
+pub enum TypeDescriptor {
+    Fixed(PluginType),
+    Dependent {
+        default: PluginType,
+        patterns: Vec<(Dependencies, PluginType)>,
+    },
+}
+
+impl Default for TypeDescriptor {
+    fn default() -> Self {
+        TypeDescriptor::Fixed(PluginType::Optional)
+    }
+}
+
+impl TypeDescriptor {
+    fn parse(elem: &Element) -> Result<Self, Error> {
+        let type_name = |elem: &Element| -> Result<PluginType, Error> {
+            Ok(attr(elem, "name")?.parse().unwrap_or(PluginType::Optional))
+        };
+        if let Ok(ty) = child(elem, "type") {
+            return Ok(TypeDescriptor::Fixed(type_name(ty)?));
+        }
+        let dep_type = child(elem, "dependencyType")?;
+        Ok(TypeDescriptor::Dependent {
+            default: type_name(child(dep_type, "defaultType")?)?,
+            patterns: match child(dep_type, "patterns") {
+                Ok(patterns) => children(patterns, "pattern")
+                    .map(|pattern| {
+                        Ok((
+                            Dependencies::parse(child(pattern, "dependencies")?)?,
+                            type_name(child(pattern, "type")?)?,
+                        ))
+                    })
+                    .collect::<Result<_, Error>>()?,
+                Err(_) => Vec::new(),
+            },
+        })
+    }
+    /// Get the plugin type given the current state of the installation
+    pub fn resolve(&self, env: &Environment, flags: &Flags) -> PluginType {
+        match self {
+            TypeDescriptor::Fixed(ty) => *ty,
+            TypeDescriptor::Dependent { default, patterns } => patterns
+                .iter()
+                .find(|(deps, _)| deps.eval(env, flags))
+                .map_or(*default, |(_, ty)| *ty),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Plugin {
     pub name: String,
     pub description: String,
-    pub files: Vec<PathBuf>,
+    pub files: Vec<FileInstall>,
+    pub condition_flags: Vec<(String, String)>,
+    pub ty: TypeDescriptor,
 }
 
 #[derive(Debug)]
@@ -44,14 +264,20 @@ pub struct Group {
 #[derive(Debug)]
 pub struct InstallStep {
     pub name: String,
+    pub visible: Option<Dependencies>,
     pub groups: Vec<Group>,
 }
 
 #[derive(Debug)]
 pub struct Fomod {
     pub name: String,
-    pub required: Vec<PathBuf>,
+    /// The path of the mod's image relative to the config's root folder
+    pub image: Option<PathBuf>,
+    /// Files that must be installed for the mod to work
+    pub dependencies: Option<Dependencies>,
+    pub required: Vec<FileInstall>,
     pub install_steps: Vec<InstallStep>,
+    pub conditional: Vec<ConditionalPattern>,
 }
 
 impl Fomod {
@@ -60,97 +286,171 @@ impl Fomod {
         C: Read,
     {
         let config_tree = Element::parse(config)?;
-        let fomod = Fomod {
-            // Name
+        Ok(Fomod {
             name: child_text(&config_tree, "moduleName")?.into(),
-
-            // Required install files
-            required: {
-                let required_install_files = child(&config_tree, "requiredInstallFiles");
-                required_install_files
-                    .into_iter()
-                    .map(|rif| children_attributes(&rif, "file", "source"))
-                    .flatten()
-                    .map(Into::into)
-                    .collect()
+            image: child(&config_tree, "moduleImage")
+                .ok()
+                .and_then(|image| image.attributes.get("path"))
+                .map(|path| normalize(path)),
+            dependencies: child(&config_tree, "moduleDependencies")
+                .ok()
+                .map(Dependencies::parse)
+                .transpose()?,
+            required: match child(&config_tree, "requiredInstallFiles") {
+                Ok(files) => file_list(files)?,
+                Err(_) => Vec::new(),
             },
-
-            // Install steps
-            install_steps: {
-                let install_steps = child(&config_tree, "installSteps")?;
-                children(&install_steps, "installStep")
-                    .map(|step| {
-                        Ok(InstallStep {
-                            // Name
-                            name: step.attributes.get("name").unwrap().into(),
-
-                            // Optional file groups
-                            groups: {
-                                let groups = child(&step, "optionalFileGroups")?;
-                                children(&groups, "group")
-                                    .map(|group| {
-                                        Ok(Group {
-                                            // Name
-                                            name: group.attributes.get("name").unwrap().into(),
-
-                                            // Type
-                                            ty: toml::from_str(
-                                                group.attributes.get("type").unwrap(),
-                                            )
-                                            .unwrap_or(GroupType::SelectAny),
-
-                                            // Plugins
-                                            plugins: {
-                                                let plugins = child(&group, "plugins")?;
-                                                children(&plugins, "plugin")
-                                                    .map(|plugin| {
-                                                        Ok(Plugin {
-                                                            // Name
-                                                            name: plugin
-                                                                .attributes
-                                                                .get("name")
-                                                                .unwrap()
-                                                                .into(),
-
-                                                            // Description
-                                                            description: child_text(
-                                                                plugin,
-                                                                "description",
-                                                            )
-                                                            .unwrap_or_default()
-                                                            .into(),
-
-                                                            // Files
-                                                            files: {
-                                                                child(plugin, "files")
-                                                                    .into_iter()
-                                                                    .map(|files| {
-                                                                        children_attributes(
-                                                                            &files, "file",
-                                                                            "source",
-                                                                        )
-                                                                    })
-                                                                    .flatten()
-                                                                    .map(Into::into)
-                                                                    .collect()
-                                                            },
-                                                        })
-                                                    })
-                                                    .collect::<Result<Vec<_>, Error>>()?
-                                            },
-                                        })
-                                    })
-                                    .collect::<Result<Vec<_>, Error>>()?
-                            },
-                        })
-                    })
-                    .collect::<Result<Vec<_>, Error>>()?
+            install_steps: match child(&config_tree, "installSteps") {
+                Ok(steps) => ordered(steps, "installStep", parse_step, |step| &step.name)?,
+                Err(_) => Vec::new(),
             },
+            conditional: match child(&config_tree, "conditionalFileInstalls") {
+                Ok(conditional) => condition::parse_patterns(conditional)?,
+                Err(_) => Vec::new(),
+            },
+        })
+    }
+    /// Find and parse the Fomod config in an extracted mod
+    ///
+    /// Returns the folder, relative to the extracted folder, that the config's file
+    /// sources are relative to along with the config
+    pub fn find(extracted: &Path) -> crate::Result<Option<(PathBuf, Self)>> {
+        let config_path = if let Some(path) = find_config(extracted) {
+            path
+        } else {
+            return Ok(None);
         };
-        Ok(fomod)
+        let root = config_path
+            .parent()
+            .and_then(Path::parent)
+            .and_then(|root| diff_paths(root, extracted))
+            .unwrap_or_default();
+        let fomod = Fomod::parse(read_xml(&config_path)?.as_bytes())?;
+        Ok(Some((root, fomod)))
     }
 }
 
+/// Find the path to the Fomod config in an extracted mod
+fn find_config(extracted: &Path) -> Option<PathBuf> {
+    utils::find_file(extracted, "ModuleConfig.xml")
+}
+
+/// Read an XML file in whatever encoding it uses
+///
+/// The XML declaration is removed since the text is no longer in the declared encoding.
+pub fn read_xml<P>(path: P) -> crate::Result<String>
+where
+    P: AsRef<Path>,
+{
+    let bytes = fs::read(path)?;
+    // UTF-16 without a byte order mark
+    let unmarked = match bytes.get(..2) {
+        Some([b'<', 0]) => Some(UTF_16LE),
+        Some([0, b'<']) => Some(UTF_16BE),
+        _ => None,
+    };
+    // A declaration that can be read as ASCII cannot be in UTF-16, whatever it says
+    let declared = String::from_utf8_lossy(&bytes[..bytes.len().min(200)])
+        .split("encoding=")
+        .nth(1)
+        .and_then(|rest| rest.get(1..))
+        .and_then(|rest| rest.split(['"', '\'']).next())
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+        .filter(|&encoding| encoding != UTF_16LE && encoding != UTF_16BE);
+    let encoding = Encoding::for_bom(&bytes)
+        .map(|(encoding, _)| encoding)
+        .or(unmarked)
+        .or(declared)
+        .unwrap_or(UTF_8);
+    let (text, _, _) = encoding.decode(&bytes);
+    let text = text.trim_start();
+    let text = if text.starts_with("<?xml") {
+        text.find("?>").map_or(text, |end| &text[end + 2..])
+    } else {
+        text
+    };
+    Ok(text.into())
+}
+
+fn parse_step(step: &Element) -> Result<InstallStep, Error> {
+    Ok(InstallStep {
+        name: attr(step, "name")?.into(),
+        visible: child(step, "visible")
+            .ok()
+            .map(Dependencies::parse)
+            .transpose()?,
+        groups: ordered(
+            child(step, "optionalFileGroups")?,
+            "group",
+            parse_group,
+            |group| &group.name,
+        )?,
+    })
+}
+
+fn parse_group(group: &Element) -> Result<Group, Error> {
+    Ok(Group {
+        name: attr(group, "name")?.into(),
+        ty: attr(group, "type")?.parse().unwrap_or(GroupType::SelectAny),
+        plugins: ordered(child(group, "plugins")?, "plugin", parse_plugin, |plugin| {
+            &plugin.name
+        })?,
+    })
+}
+
+fn parse_plugin(plugin: &Element) -> Result<Plugin, Error> {
+    Ok(Plugin {
+        name: attr(plugin, "name")?.into(),
+        description: child_text(plugin, "description")
+            .unwrap_or_default()
+            .trim()
+            .into(),
+        files: match child(plugin, "files") {
+            Ok(files) => file_list(files)?,
+            Err(_) => Vec::new(),
+        },
+        condition_flags: child(plugin, "conditionFlags")
+            .map(|flags| {
+                children(flags, "flag")
+                    .filter_map(|flag| {
+                        let name = flag.attributes.get("name")?.clone();
+                        let value = flag.get_text().unwrap_or_default().trim().into();
+                        Some((name, value))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+        ty: match child(plugin, "typeDescriptor") {
+            Ok(ty) => TypeDescriptor::parse(ty)?,
+            Err(_) => TypeDescriptor::default(),
+        },
+    })
+}
+
+/// Parse the children of a list element and sort them by its `order` attribute
+fn ordered<T, P, N>(list: &Element, name: &str, parse: P, item_name: N) -> Result<Vec<T>, Error>
+where
+    P: Fn(&Element) -> Result<T, Error>,
+    N: Fn(&T) -> &str,
+{
+    let mut items = children(list, name)
+        .map(parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    Order::of(list).sort(&mut items, item_name);
+    Ok(items)
+}
+
+/// Parse all files and folders in a file list
+fn file_list(files: &Element) -> Result<Vec<FileInstall>, Error> {
+    files
+        .children
+        .iter()
+        .filter_map(|node| node.as_element())
+        .filter(|elem| elem.name == "file" || elem.name == "folder")
+        .map(FileInstall::parse)
+        .collect()
+}
+
 fn child<'a>(elem: &'a Element, name: &str) -> Result<&'a Element, Error> {
     elem.get_child(name)
         .ok_or_else(|| Error::Missing(name.into()))
@@ -163,55 +463,56 @@ fn children<'a>(elem: &'a Element, name: &'a str) -> impl Iterator<Item = &'a El
         .filter(move |elem| elem.name == name)
 }
 
-fn children_attributes<'a>(
-    elem: &'a Element,
-    name: &'a str,
-    attr: &'a str,
-) -> impl Iterator<Item = &'a str> + 'a {
-    children(elem, name).filter_map(move |elem| elem.attributes.get(attr).map(|s| s.as_str()))
+fn attr<'a>(elem: &'a Element, name: &str) -> Result<&'a str, Error> {
+    elem.attributes
+        .get(name)
+        .map(String::as_str)
+        .ok_or_else(|| Error::MissingAttribute {
+            elem: elem.name.clone(),
+            attr: name.into(),
+        })
 }
 
 fn child_text<'a>(elem: &'a Element, name: &str) -> Result<Cow<'a, str>, Error> {
-    child(elem, name).map(|elem| elem.get_text().unwrap())
+    child(elem, name).map(|elem| elem.get_text().unwrap_or_default())
 }
 
-pub fn pseudo_fomod<P>(mod_name: &str, top: P) -> crate::Result<Vec<PathBuf>>
+pub fn pseudo_fomod<P>(
+    mod_name: &str,
+    top: P,
+    data_folder: Option<&Path>,
+    rules: &RootRules,
+) -> crate::Result<Vec<FileMapping>>
 where
     P: AsRef<Path>,
 {
-    let mut path = top.as_ref().to_path_buf();
+    let top = top.as_ref();
     // Find main folder
-    while fs::read_dir(&path)?.filter_map(Result::ok).count() == 1 {
-        path = fs::read_dir(&path)?
-            .filter_map(Result::ok)
-            .next()
-            .unwrap()
-            .path();
-    }
+    let path = top.join(rules.find_root(top, data_folder)?.0);
     let mut install_paths = Vec::new();
     let mut entries: Vec<_> = fs::read_dir(&path)?.filter_map(Result::ok).collect();
     entries.sort_by_key(|entry| entry.path());
     let mut any_parts = false;
     for entry in entries {
         if entry.file_type()?.is_dir() {
-            let lowest = entry.path().iter().last().unwrap().to_owned();
+            let lowest = entry.file_name();
             let starts_with_num = lowest
                 .to_string_lossy()
                 .chars()
                 .next()
-                .map_or(false, |c| c.is_digit(10));
+                .is_some_and(|c| c.is_ascii_digit());
             if starts_with_num {
                 if !any_parts {
                     println!(
-                        "{:?} has a Fomod installer, but clim does not currently support it. \
+                        "{:?} has a Fomod installer that clim could not read. \
                         You can still select which sections you want to install.",
                         mod_name
                     );
                     any_parts = true;
                 }
-                print!("Would you like to install {:?}? (yes/no) ", lowest);
-                stdout().flush()?;
-                let input = stdin().lock().lines().next().unwrap()?.to_lowercase();
+                let input =
+                    utils::prompt(format!("Would you like to install {:?}? (yes/no) ", lowest))?
+                        .to_lowercase();
                 if input.starts_with('y') {
                     install_paths.push(entry.path());
                 } else if !input.starts_with('n') {
@@ -221,7 +522,10 @@ where
         }
     }
     if !any_parts {
-        return Ok(vec![path]);
+        install_paths.push(path);
     }
-    Ok(install_paths)
+    Ok(install_paths
+        .into_iter()
+        .map(|path| FileMapping::folder(diff_paths(path, top).unwrap_or_default()))
+        .collect())
 }
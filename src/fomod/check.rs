@@ -0,0 +1,390 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::Path,
+};
+
+use xmltree::Element;
+
+use super::{
+    children, find_config, normalize, read_xml, Fomod, GroupType, Order, PluginType, Resolver,
+};
+
+/// A problem found in a Fomod config
+#[derive(Debug, Clone)]
+pub struct Problem {
+    /// The path to the element with the problem
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Check the Fomod config in an extracted mod for problems
+pub fn check(extracted: &Path) -> crate::Result<Vec<Problem>> {
+    let config_path = if let Some(path) = find_config(extracted) {
+        path
+    } else {
+        return Ok(vec![Problem {
+            path: extracted.to_string_lossy().into(),
+            message: "No fomod/ModuleConfig.xml found".into(),
+        }]);
+    };
+    let root = config_path
+        .parent()
+        .and_then(Path::parent)
+        .unwrap_or(extracted);
+    let text = read_xml(&config_path)?;
+    let tree = match Element::parse(text.as_bytes()) {
+        Ok(tree) => tree,
+        Err(e) => {
+            return Ok(vec![Problem {
+                path: config_path.to_string_lossy().into(),
+                message: e.to_string(),
+            }])
+        }
+    };
+    let mut checker = Checker {
+        root,
+        resolver: Resolver::new(root, Path::new("")),
+        problems: Vec::new(),
+    };
+    checker.config(&tree);
+    // Anything the parser rejects that was not already reported
+    if let Err(e) = Fomod::parse(text.as_bytes()) {
+        if checker.problems.is_empty() {
+            checker.problem(&tree.name, e.to_string());
+        }
+    }
+    Ok(checker.problems)
+}
+
+struct Checker<'a> {
+    root: &'a Path,
+    resolver: Resolver,
+    problems: Vec<Problem>,
+}
+
+/// Flag values that may have been set, by flag name
+type FlagValues = HashMap<String, HashSet<String>>;
+
+impl<'a> Checker<'a> {
+    fn problem<S>(&mut self, path: &str, message: S)
+    where
+        S: Into<String>,
+    {
+        self.problems.push(Problem {
+            path: path.into(),
+            message: message.into(),
+        });
+    }
+    fn attr<'e>(&mut self, elem: &'e Element, path: &str, name: &str) -> Option<&'e str> {
+        let value = elem.attributes.get(name).map(String::as_str);
+        if value.is_none() {
+            self.problem(path, format!("Missing attribute {:?}", name));
+        }
+        value
+    }
+    fn child<'e>(&mut self, elem: &'e Element, path: &str, name: &str) -> Option<&'e Element> {
+        let child = elem.get_child(name);
+        if child.is_none() {
+            self.problem(path, format!("Missing element {:?}", name));
+        }
+        child
+    }
+    fn config(&mut self, config: &Element) {
+        let path = config.name.clone();
+        if config.name != "config" {
+            self.problem(&path, "The root element should be \"config\"");
+        }
+        self.child(config, &path, "moduleName");
+        let mut flags = FlagValues::new();
+        if let Some(image) = config.get_child("moduleImage") {
+            let image_path = format!("{}/moduleImage", path);
+            if let Some(source) = self.attr(image, &image_path, "path") {
+                if self.resolver.resolve(&normalize(source)).is_none() {
+                    self.problem(&image_path, format!("Image {:?} does not exist", source));
+                }
+            }
+        }
+        if let Some(deps) = config.get_child("moduleDependencies") {
+            self.dependencies(deps, &format!("{}/moduleDependencies", path), &flags);
+        }
+        if let Some(required) = config.get_child("requiredInstallFiles") {
+            self.files(required, &format!("{}/requiredInstallFiles", path));
+        }
+        if let Some(steps) = config.get_child("installSteps") {
+            let steps_path = format!("{}/installSteps", path);
+            self.order(steps, &steps_path);
+            // Flags can only be set by steps that come before in the install order
+            let mut ordered: Vec<_> = children(steps, "installStep").enumerate().collect();
+            Order::of(steps).sort(&mut ordered, |(_, step)| {
+                step.attributes.get("name").map_or("", String::as_str)
+            });
+            let mut names = HashSet::new();
+            for (i, step) in ordered {
+                let step_path = element_path(&steps_path, step, i);
+                if let Some(name) = self.attr(step, &step_path, "name") {
+                    if !names.insert(name) {
+                        self.problem(&step_path, "Duplicate step name");
+                    }
+                }
+                self.step(step, &step_path, &mut flags);
+            }
+        }
+        if let Some(patterns) = config
+            .get_child("conditionalFileInstalls")
+            .and_then(|cfi| cfi.get_child("patterns"))
+        {
+            let patterns_path = format!("{}/conditionalFileInstalls/patterns", path);
+            for (i, pattern) in children(patterns, "pattern").enumerate() {
+                let pattern_path = element_path(&patterns_path, pattern, i);
+                if let Some(deps) = self.child(pattern, &pattern_path, "dependencies") {
+                    let deps_path = format!("{}/dependencies", pattern_path);
+                    self.dependencies(deps, &deps_path, &flags);
+                    if !reachable(deps, &flags) {
+                        self.problem(&pattern_path, "The conditions can never be met");
+                    }
+                }
+                if let Some(files) = self.child(pattern, &pattern_path, "files") {
+                    self.files(files, &format!("{}/files", pattern_path));
+                }
+            }
+        }
+    }
+    fn step(&mut self, step: &Element, path: &str, flags: &mut FlagValues) {
+        if let Some(visible) = step.get_child("visible") {
+            let visible_path = format!("{}/visible", path);
+            self.dependencies(visible, &visible_path, flags);
+            if !reachable(visible, flags) {
+                self.problem(path, "The step can never be visible");
+            }
+        }
+        let groups = if let Some(groups) = self.child(step, path, "optionalFileGroups") {
+            groups
+        } else {
+            return;
+        };
+        let groups_path = format!("{}/optionalFileGroups", path);
+        let mut step_flags = FlagValues::new();
+        self.order(groups, &groups_path);
+        let mut group_names = HashSet::new();
+        for (i, group) in children(groups, "group").enumerate() {
+            let group_path = element_path(&groups_path, group, i);
+            if let Some(name) = self.attr(group, &group_path, "name") {
+                if !group_names.insert(name) {
+                    self.problem(&group_path, "Duplicate group name");
+                }
+            }
+            if let Some(ty) = self.attr(group, &group_path, "type") {
+                if ty.parse::<GroupType>().is_err() {
+                    self.problem(&group_path, format!("Unknown group type {:?}", ty));
+                }
+            }
+            let plugins = if let Some(plugins) = self.child(group, &group_path, "plugins") {
+                plugins
+            } else {
+                continue;
+            };
+            let plugins_path = format!("{}/plugins", group_path);
+            self.order(plugins, &plugins_path);
+            let mut plugin_names = HashSet::new();
+            let mut count = 0;
+            for (j, plugin) in children(plugins, "plugin").enumerate() {
+                count += 1;
+                let plugin_path = element_path(&plugins_path, plugin, j);
+                if let Some(name) = self.attr(plugin, &plugin_path, "name") {
+                    if !plugin_names.insert(name) {
+                        self.problem(&plugin_path, "Duplicate plugin name");
+                    }
+                }
+                self.plugin(plugin, &plugin_path, flags, &mut step_flags);
+            }
+            if count == 0 {
+                self.problem(&plugins_path, "The group has no plugins");
+            }
+        }
+        // Flags set in this step are only available to later steps
+        for (flag, values) in step_flags {
+            flags.entry(flag).or_default().extend(values);
+        }
+    }
+    fn plugin(
+        &mut self,
+        plugin: &Element,
+        path: &str,
+        flags: &FlagValues,
+        step_flags: &mut FlagValues,
+    ) {
+        if plugin.get_child("description").is_none() {
+            self.problem(path, "Missing element \"description\"");
+        }
+        if let Some(files) = plugin.get_child("files") {
+            self.files(files, &format!("{}/files", path));
+        }
+        if let Some(condition_flags) = plugin.get_child("conditionFlags") {
+            for (i, flag) in children(condition_flags, "flag").enumerate() {
+                let flag_path = element_path(&format!("{}/conditionFlags", path), flag, i);
+                if let Some(name) = self.attr(flag, &flag_path, "name") {
+                    let value = flag.get_text().unwrap_or_default().trim().to_string();
+                    step_flags.entry(name.into()).or_default().insert(value);
+                }
+            }
+        }
+        let ty_path = format!("{}/typeDescriptor", path);
+        let ty = if let Some(ty) = self.child(plugin, path, "typeDescriptor") {
+            ty
+        } else {
+            return;
+        };
+        if let Some(simple) = ty.get_child("type") {
+            self.plugin_type(simple, &format!("{}/type", ty_path));
+        } else if let Some(dep_type) = ty.get_child("dependencyType") {
+            let dep_path = format!("{}/dependencyType", ty_path);
+            if let Some(default) = self.child(dep_type, &dep_path, "defaultType") {
+                self.plugin_type(default, &format!("{}/defaultType", dep_path));
+            }
+            if let Some(patterns) = dep_type.get_child("patterns") {
+                let patterns_path = format!("{}/patterns", dep_path);
+                for (i, pattern) in children(patterns, "pattern").enumerate() {
+                    let pattern_path = element_path(&patterns_path, pattern, i);
+                    if let Some(deps) = self.child(pattern, &pattern_path, "dependencies") {
+                        self.dependencies(deps, &format!("{}/dependencies", pattern_path), flags);
+                    }
+                    if let Some(simple) = self.child(pattern, &pattern_path, "type") {
+                        self.plugin_type(simple, &format!("{}/type", pattern_path));
+                    }
+                }
+            }
+        } else {
+            self.problem(&ty_path, "Missing element \"type\" or \"dependencyType\"");
+        }
+    }
+    fn plugin_type(&mut self, elem: &Element, path: &str) {
+        if let Some(name) = self.attr(elem, path, "name") {
+            if name.parse::<PluginType>().is_err() {
+                self.problem(path, format!("Unknown plugin type {:?}", name));
+            }
+        }
+    }
+    fn order(&mut self, list: &Element, path: &str) {
+        if let Some(order) = list.attributes.get("order") {
+            if order.parse::<Order>().is_err() {
+                self.problem(path, format!("Unknown order {:?}", order));
+            }
+        }
+    }
+    fn dependencies(&mut self, deps: &Element, path: &str, flags: &FlagValues) {
+        if let Some(operator) = deps.attributes.get("operator") {
+            if operator != "And" && operator != "Or" {
+                self.problem(path, format!("Unknown operator {:?}", operator));
+            }
+        }
+        for (i, dep) in deps
+            .children
+            .iter()
+            .filter_map(|node| node.as_element())
+            .enumerate()
+        {
+            let dep_path = element_path(path, dep, i);
+            match dep.name.as_str() {
+                "fileDependency" => {
+                    self.attr(dep, &dep_path, "file");
+                    if let Some(state) = self.attr(dep, &dep_path, "state") {
+                        if !["Missing", "Inactive", "Active"].contains(&state) {
+                            self.problem(&dep_path, format!("Unknown file state {:?}", state));
+                        }
+                    }
+                }
+                "flagDependency" => {
+                    if let Some(flag) = self.attr(dep, &dep_path, "flag") {
+                        if !flags.contains_key(flag) {
+                            self.problem(
+                                &dep_path,
+                                format!("Flag {:?} is not set by any earlier plugin", flag),
+                            );
+                        }
+                    }
+                    self.attr(dep, &dep_path, "value");
+                }
+                "gameDependency" | "fommDependency" => {
+                    self.attr(dep, &dep_path, "version");
+                }
+                "dependencies" => self.dependencies(dep, &dep_path, flags),
+                name => self.problem(&dep_path, format!("Unknown dependency {:?}", name)),
+            }
+        }
+    }
+    fn files(&mut self, files: &Element, path: &str) {
+        for (i, file) in files
+            .children
+            .iter()
+            .filter_map(|node| node.as_element())
+            .enumerate()
+        {
+            let file_path = element_path(path, file, i);
+            if file.name != "file" && file.name != "folder" {
+                self.problem(&file_path, format!("Unknown element {:?}", file.name));
+                continue;
+            }
+            let source = if let Some(source) = self.attr(file, &file_path, "source") {
+                source
+            } else {
+                continue;
+            };
+            let full = self
+                .resolver
+                .resolve(&normalize(source))
+                .map(|source| self.root.join(source));
+            if file.name == "folder" && !full.as_ref().is_some_and(|full| full.is_dir()) {
+                self.problem(&file_path, format!("Folder {:?} does not exist", source));
+            } else if file.name == "file" && !full.as_ref().is_some_and(|full| full.is_file()) {
+                self.problem(&file_path, format!("File {:?} does not exist", source));
+            }
+            if let Some(priority) = file.attributes.get("priority") {
+                if priority.parse::<i32>().is_err() {
+                    self.problem(&file_path, format!("Invalid priority {:?}", priority));
+                }
+            }
+        }
+    }
+}
+
+/// Get the path to a child element, including its index and name if it has one
+fn element_path(parent: &str, elem: &Element, i: usize) -> String {
+    if let Some(name) = elem.attributes.get("name") {
+        format!("{}/{}[{}]({:?})", parent, elem.name, i + 1, name)
+    } else {
+        format!("{}/{}[{}]", parent, elem.name, i + 1)
+    }
+}
+
+/// Check if dependencies could possibly be met given the flags that may have been set
+fn reachable(deps: &Element, flags: &FlagValues) -> bool {
+    let mut results = deps
+        .children
+        .iter()
+        .filter_map(|node| node.as_element())
+        .map(|dep| match dep.name.as_str() {
+            "flagDependency" => {
+                let flag = dep.attributes.get("flag").map(String::as_str);
+                let value = dep.attributes.get("value").map_or("", String::as_str);
+                // Unset flags compare equal to an empty value
+                value.is_empty()
+                    || flag
+                        .and_then(|flag| flags.get(flag))
+                        .is_some_and(|values| values.contains(value))
+            }
+            "dependencies" => reachable(dep, flags),
+            _ => true,
+        });
+    if deps.attributes.get("operator").map(String::as_str) == Some("Or") {
+        results.any(|res| res)
+    } else {
+        results.all(|res| res)
+    }
+}
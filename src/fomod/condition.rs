@@ -0,0 +1,220 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use xmltree::Element;
+
+use crate::utils;
+
+use super::{attr, child, children, file_list, normalize, paths, Error, FileInstall};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileState {
+    Missing,
+    Inactive,
+    Active,
+}
+
+impl FileState {
+    pub fn description(self) -> &'static str {
+        match self {
+            FileState::Missing => "missing",
+            FileState::Inactive => "inactive",
+            FileState::Active => "active",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Condition {
+    File { file: String, state: FileState },
+    Flag { flag: String, value: String },
+    Game,
+    Fomm,
+    Nested(Dependencies),
+}
+
+/// A set of conditions joined by an operator
+#[derive(Debug, Clone)]
+pub struct Dependencies {
+    pub operator: Operator,
+    pub conditions: Vec<Condition>,
+}
+
+/// Files that are installed if their dependencies are met once all steps are done
+#[derive(Debug, Clone)]
+pub struct ConditionalPattern {
+    pub dependencies: Dependencies,
+    pub files: Vec<FileInstall>,
+}
+
+impl Dependencies {
+    pub fn parse(elem: &Element) -> Result<Self, Error> {
+        let operator = match elem.attributes.get("operator").map(String::as_str) {
+            Some("Or") => Operator::Or,
+            _ => Operator::And,
+        };
+        let mut conditions = Vec::new();
+        for dep in elem.children.iter().filter_map(|node| node.as_element()) {
+            conditions.push(match dep.name.as_str() {
+                "fileDependency" => Condition::File {
+                    file: attr(dep, "file")?.into(),
+                    state: match attr(dep, "state")? {
+                        "Active" => FileState::Active,
+                        "Inactive" => FileState::Inactive,
+                        _ => FileState::Missing,
+                    },
+                },
+                "flagDependency" => Condition::Flag {
+                    flag: attr(dep, "flag")?.into(),
+                    value: attr(dep, "value")?.into(),
+                },
+                "gameDependency" => Condition::Game,
+                "fommDependency" => Condition::Fomm,
+                "dependencies" => Condition::Nested(Dependencies::parse(dep)?),
+                _ => continue,
+            });
+        }
+        Ok(Dependencies {
+            operator,
+            conditions,
+        })
+    }
+    /// Check if the dependencies are met
+    pub fn eval(&self, env: &Environment, flags: &Flags) -> bool {
+        let mut results = self.conditions.iter().map(|cond| match cond {
+            Condition::File { file, state } => env.file_state(file) == *state,
+            Condition::Flag { flag, value } => {
+                flags.get(flag).map(String::as_str).unwrap_or_default() == value
+            }
+            // There is no reliable way to know the game or mod manager version
+            Condition::Game | Condition::Fomm => true,
+            Condition::Nested(deps) => deps.eval(env, flags),
+        });
+        match self.operator {
+            Operator::And => results.all(|res| res),
+            Operator::Or => results.any(|res| res),
+        }
+    }
+    /// Describe the conditions that keep the dependencies from being met
+    pub fn unmet(&self, env: &Environment, flags: &Flags) -> Vec<String> {
+        if self.eval(env, flags) {
+            return Vec::new();
+        }
+        self.conditions
+            .iter()
+            .flat_map(|cond| match cond {
+                Condition::File { file, state } => {
+                    let actual = env.file_state(file);
+                    if actual == *state {
+                        Vec::new()
+                    } else {
+                        vec![format!(
+                            "{:?} should be {} but is {}",
+                            file,
+                            state.description(),
+                            actual.description()
+                        )]
+                    }
+                }
+                Condition::Flag { flag, value } => {
+                    if flags.get(flag).map(String::as_str).unwrap_or_default() == value {
+                        Vec::new()
+                    } else {
+                        vec![format!("flag {:?} should be {:?}", flag, value)]
+                    }
+                }
+                Condition::Game | Condition::Fomm => Vec::new(),
+                Condition::Nested(deps) => deps.unmet(env, flags),
+            })
+            .collect()
+    }
+}
+
+pub(super) fn parse_patterns(conditional: &Element) -> Result<Vec<ConditionalPattern>, Error> {
+    children(child(conditional, "patterns")?, "pattern")
+        .map(|pattern| {
+            Ok(ConditionalPattern {
+                dependencies: Dependencies::parse(child(pattern, "dependencies")?)?,
+                files: match child(pattern, "files") {
+                    Ok(files) => file_list(files)?,
+                    Err(_) => Vec::new(),
+                },
+            })
+        })
+        .collect()
+}
+
+/// Condition flags set by selected plugins
+pub type Flags = HashMap<String, String>;
+
+/// The state of the game that file dependencies are checked against
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    /// The game folder, which is checked for files not in the data folder
+    pub game_dir: PathBuf,
+    /// The folder that dependency paths are relative to
+    pub data_dir: PathBuf,
+    /// Lowercase names of active plugins
+    pub active_plugins: HashSet<String>,
+    /// Lookup keys of the files that enabled mods install, which may not be deployed yet
+    pub mod_files: HashSet<String>,
+}
+
+impl Environment {
+    /// Create an environment from the files of enabled mods, relative to the game folder
+    pub fn new<I>(game_dir: PathBuf, data_dir: PathBuf, mod_files: I) -> Self
+    where
+        I: IntoIterator<Item = PathBuf>,
+    {
+        let mod_files: Vec<PathBuf> = mod_files.into_iter().collect();
+        Environment {
+            active_plugins: mod_files
+                .iter()
+                .filter(|file| utils::is_plugin(file))
+                .filter_map(|file| file.file_name())
+                .map(|name| name.to_string_lossy().to_lowercase())
+                .collect(),
+            mod_files: mod_files
+                .iter()
+                .map(|file| paths::key(&game_dir.join(file)))
+                .collect(),
+            game_dir,
+            data_dir,
+        }
+    }
+    /// Check if a file exists in the data folder or game folder or is installed by a mod
+    fn exists(&self, path: &Path) -> bool {
+        [&self.data_dir, &self.game_dir].iter().any(|dir| {
+            self.mod_files.contains(&paths::key(&dir.join(path)))
+                || utils::find_case_insensitive(dir, path).is_some()
+        })
+    }
+    pub fn file_state(&self, file: &str) -> FileState {
+        let path = normalize(file);
+        let is_plugin = utils::is_plugin(&path);
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if is_plugin && self.active_plugins.contains(&name) {
+            FileState::Active
+        } else if self.exists(&path) {
+            // Masters in the data folder are always loaded
+            if is_plugin && !name.ends_with(".esm") {
+                FileState::Inactive
+            } else {
+                FileState::Active
+            }
+        } else {
+            FileState::Missing
+        }
+    }
+}
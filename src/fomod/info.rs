@@ -0,0 +1,81 @@
+use std::path::Path;
+
+use serde_derive::{Deserialize, Serialize};
+use walkdir::{DirEntry, WalkDir};
+use xmltree::Element;
+
+use super::{children, read_xml, Error};
+
+/// Mod metadata from a Fomod info.xml
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub website: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+}
+
+impl ModInfo {
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let tree = Element::parse(text.as_bytes())?;
+        // Element names are not consistently capitalized
+        let field = |name: &str| {
+            tree.children
+                .iter()
+                .filter_map(|node| node.as_element())
+                .find(|elem| elem.name.eq_ignore_ascii_case(name))
+                .and_then(|elem| elem.get_text())
+                .map(|text| text.trim().to_string())
+                .filter(|text| !text.is_empty())
+        };
+        Ok(ModInfo {
+            name: field("Name"),
+            author: field("Author"),
+            version: field("Version"),
+            website: field("Website"),
+            description: field("Description"),
+            groups: tree
+                .children
+                .iter()
+                .filter_map(|node| node.as_element())
+                .find(|elem| elem.name.eq_ignore_ascii_case("Groups"))
+                .map(|groups| {
+                    children(groups, "element")
+                        .filter_map(|elem| elem.get_text())
+                        .map(|text| text.trim().to_string())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+    }
+    /// Find and parse the info.xml in an extracted mod
+    pub fn find(extracted: &Path) -> crate::Result<Option<Self>> {
+        let path = WalkDir::new(extracted)
+            .into_iter()
+            .filter_map(Result::ok)
+            .map(DirEntry::into_path)
+            .find(|path| {
+                let named = |path: &Path, name: &str| {
+                    path.file_name().is_some_and(|file_name| {
+                        file_name.to_string_lossy().eq_ignore_ascii_case(name)
+                    })
+                };
+                named(path, "info.xml")
+                    && path.parent().is_some_and(|parent| named(parent, "fomod"))
+            });
+        if let Some(path) = path {
+            Ok(Some(ModInfo::parse(&read_xml(&path)?)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,421 @@
+use std::{fs, path::Path};
+
+use indexmap::IndexMap;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{colorln, game::FileMapping, utils};
+
+use super::{Environment, Flags, Fomod, Group, GroupType, PluginType, Resolver};
+
+/// Plugin names by step and group
+pub type Choices = IndexMap<String, IndexMap<String, Vec<String>>>;
+
+/// The answers given to an installer
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Answers {
+    /// The names of the selected plugins
+    pub selected: Choices,
+    /// The names of the plugins that were available
+    pub options: Choices,
+}
+
+impl Answers {
+    pub fn is_empty(&self) -> bool {
+        self.selected.is_empty()
+    }
+    fn get<'a>(choices: &'a Choices, step: &str, group: &str) -> Option<&'a Vec<String>> {
+        choices.get(step).and_then(|groups| groups.get(group))
+    }
+    /// Add choices that take precedence over existing ones
+    fn merge(&mut self, choices: &Choices) {
+        for (step, groups) in choices {
+            for (group, plugins) in groups {
+                self.selected
+                    .entry(step.clone())
+                    .or_default()
+                    .insert(group.clone(), plugins.clone());
+                if let Some(options) = self.options.get_mut(step) {
+                    options.shift_remove(group);
+                }
+            }
+        }
+    }
+    fn insert(&mut self, step: &str, group: &Group, selected: &[usize]) {
+        self.selected.entry(step.into()).or_default().insert(
+            group.name.clone(),
+            selected
+                .iter()
+                .map(|&i| group.plugins[i].name.clone())
+                .collect(),
+        );
+        self.options.entry(step.into()).or_default().insert(
+            group.name.clone(),
+            group
+                .plugins
+                .iter()
+                .map(|plugin| plugin.name.clone())
+                .collect(),
+        );
+    }
+}
+
+/// How installer options without a saved answer are chosen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InstallMode {
+    /// Ask the user
+    #[default]
+    Prompt,
+    /// Take the author's recommended options without asking
+    Defaults,
+    /// Stop with an error
+    Fail,
+    /// Leave the mod uninstalled
+    Skip,
+}
+
+impl std::str::FromStr for InstallMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "prompt" => InstallMode::Prompt,
+            "defaults" => InstallMode::Defaults,
+            "fail" => InstallMode::Fail,
+            "skip" => InstallMode::Skip,
+            _ => return Err(format!("Unknown input policy {:?}", s)),
+        })
+    }
+}
+
+/// Options for running installers
+#[derive(Debug, Clone, Default)]
+pub struct InstallOptions {
+    pub mode: InstallMode,
+    /// Choices from an answers file by mod name
+    pub answers: IndexMap<String, Choices>,
+}
+
+impl InstallOptions {
+    /// Load an answers file, which maps mod names to choices
+    pub fn load_answers<P>(&mut self, path: P) -> crate::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let bytes = fs::read(path)?;
+        self.answers = toml::from_slice(&bytes)?;
+        Ok(())
+    }
+    /// Get the answers to use for a mod
+    ///
+    /// Answers from an answers file, found by mod name or installer name, take precedence
+    /// over previous answers.
+    fn answers_for(&self, mod_name: &str, fomod: &Fomod, previous: &Answers) -> Answers {
+        let mut answers = previous.clone();
+        if let Some(choices) = self
+            .answers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(mod_name))
+            .or_else(|| {
+                self.answers
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(&fomod.name))
+            })
+            .map(|(_, choices)| choices)
+        {
+            answers.merge(choices);
+        }
+        answers
+    }
+    /// Get a mod's choice for a group from an answers file, whichever step it is in
+    ///
+    /// This is for installers without steps, like BAIN packages.
+    pub fn answer(&self, mod_name: &str, group: &str) -> Option<&Vec<String>> {
+        self.answers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(mod_name))
+            .and_then(|(_, choices)| choices.values().find_map(|groups| groups.get(group)))
+    }
+}
+
+/// Check that the files a Fomod mod depends on are installed
+///
+/// Unmet dependencies are an error in `Fail` mode and a warning otherwise.
+pub fn check_dependencies(
+    mod_name: &str,
+    fomod: &Fomod,
+    env: &Environment,
+    options: &InstallOptions,
+) -> crate::Result<()> {
+    let unmet = fomod
+        .dependencies
+        .as_ref()
+        .map(|deps| deps.unmet(env, &Flags::new()))
+        .unwrap_or_default();
+    if unmet.is_empty() {
+        return Ok(());
+    }
+    if options.mode == InstallMode::Fail {
+        return Err(crate::Error::UnmetDependencies {
+            mod_name: mod_name.into(),
+            unmet,
+        });
+    }
+    colorln!(yellow, "\n{:?} has unmet requirements:", mod_name);
+    for desc in unmet {
+        colorln!(yellow, "  {}", desc);
+    }
+    Ok(())
+}
+
+/// Run a Fomod installer, returning the files that should be installed and the answers given
+///
+/// Sources are found with `resolver`. Groups answered in `previous` whose options have not changed are not asked again.
+/// Returns `None` if the mod was skipped.
+pub fn install(
+    mod_name: &str,
+    resolver: &Resolver,
+    fomod: &Fomod,
+    env: &Environment,
+    options: &InstallOptions,
+    previous: &Answers,
+) -> crate::Result<Option<(Vec<FileMapping>, Answers)>> {
+    colorln!(bold, "\nInstalling {:?} ({})", mod_name, fomod.name);
+    if let Some(image) = fomod
+        .image
+        .as_deref()
+        .and_then(|image| resolver.resolve(image))
+    {
+        colorln!(dimmed, "Image: {}", image.to_string_lossy());
+    }
+    let previous = options.answers_for(mod_name, fomod, previous);
+    let mut files: Vec<_> = fomod.required.iter().collect();
+    let mut flags = Flags::new();
+    let mut answers = Answers::default();
+    for step in &fomod.install_steps {
+        // Skip steps whose conditions are not met
+        if let Some(visible) = &step.visible {
+            if !visible.eval(env, &flags) {
+                continue;
+            }
+        }
+        colorln!(bold, "\n{}", step.name);
+        for group in &step.groups {
+            let types: Vec<PluginType> = group
+                .plugins
+                .iter()
+                .map(|plugin| plugin.ty.resolve(env, &flags))
+                .collect();
+            let selected = if let Some(selected) = replay(&previous, &step.name, group, &types) {
+                selected
+            } else if let Some(selected) = select_plugins(mod_name, group, &types, options.mode)? {
+                selected
+            } else {
+                colorln!(yellow, "Skipped {:?} because it needs input", mod_name);
+                return Ok(None);
+            };
+            answers.insert(&step.name, group, &selected);
+            for (i, plugin) in group.plugins.iter().enumerate() {
+                if selected.contains(&i) {
+                    files.extend(&plugin.files);
+                    for (flag, value) in &plugin.condition_flags {
+                        flags.insert(flag.clone(), value.clone());
+                    }
+                } else {
+                    // Some files are installed even if their plugin is not selected
+                    let usable = types[i] != PluginType::NotUsable;
+                    files.extend(
+                        plugin.files.iter().filter(|file| {
+                            file.always_install || (usable && file.install_if_usable)
+                        }),
+                    );
+                }
+            }
+        }
+    }
+    // Add conditional files
+    for pattern in &fomod.conditional {
+        if pattern.dependencies.eval(env, &flags) {
+            files.extend(&pattern.files);
+        }
+    }
+    let mut mappings = Vec::new();
+    for file in files {
+        if let Some(mapping) = file.mapping(resolver) {
+            mappings.push(mapping);
+        } else {
+            colorln!(
+                yellow,
+                "{:?} is missing {:?}",
+                mod_name,
+                file.source.to_string_lossy()
+            );
+        }
+    }
+    Ok(Some((mappings, answers)))
+}
+
+/// Try to reuse a previous answer for a group
+fn replay(
+    previous: &Answers,
+    step: &str,
+    group: &Group,
+    types: &[PluginType],
+) -> Option<Vec<usize>> {
+    let names = Answers::get(&previous.selected, step, &group.name)?;
+    // Options that changed since the last install need a new answer
+    if let Some(options) = Answers::get(&previous.options, step, &group.name) {
+        if !options
+            .iter()
+            .eq(group.plugins.iter().map(|plugin| &plugin.name))
+        {
+            colorln!(yellow, "The options for {:?} have changed", group.name);
+            return None;
+        }
+    }
+    let mut selected = Vec::new();
+    for name in names {
+        let i = group
+            .plugins
+            .iter()
+            .position(|plugin| &plugin.name == name)?;
+        if types[i] == PluginType::NotUsable {
+            return None;
+        }
+        selected.push(i);
+    }
+    selected.sort_unstable();
+    let required_missing =
+        (0..types.len()).any(|i| types[i] == PluginType::Required && !selected.contains(&i));
+    if required_missing || !group.ty.allows(selected.len(), group.plugins.len()) {
+        return None;
+    }
+    print_selection(group, &selected);
+    Some(selected)
+}
+
+fn print_selection(group: &Group, selected: &[usize]) {
+    let names: Vec<&str> = selected
+        .iter()
+        .map(|&i| group.plugins[i].name.as_str())
+        .collect();
+    if names.is_empty() {
+        println!("{}: none", group.name);
+    } else {
+        println!("{}: {}", group.name, names.join(", "));
+    }
+}
+
+/// Get the plugins that are selected by default in a group
+fn default_selection(group: &Group, types: &[PluginType]) -> Vec<usize> {
+    let usable: Vec<usize> = (0..types.len())
+        .filter(|&i| types[i] != PluginType::NotUsable)
+        .collect();
+    if group.ty == GroupType::SelectAll {
+        return usable;
+    }
+    let mut selected: Vec<usize> = usable
+        .iter()
+        .copied()
+        .filter(|&i| matches!(types[i], PluginType::Required | PluginType::Recommended))
+        .collect();
+    match group.ty {
+        GroupType::SelectExactlyOne | GroupType::SelectAtMostOne => selected.truncate(1),
+        _ => {}
+    }
+    if selected.is_empty()
+        && matches!(
+            group.ty,
+            GroupType::SelectExactlyOne | GroupType::SelectAtLeastOne
+        )
+    {
+        selected.extend(usable.first());
+    }
+    selected
+}
+
+/// Choose the plugins to install from a group
+///
+/// Returns `None` if the mod should be skipped.
+fn select_plugins(
+    mod_name: &str,
+    group: &Group,
+    types: &[PluginType],
+    mode: InstallMode,
+) -> crate::Result<Option<Vec<usize>>> {
+    let count = group.plugins.len();
+    let defaults = default_selection(group, types);
+    // Some groups leave no choice
+    let usable = types
+        .iter()
+        .filter(|&&ty| ty != PluginType::NotUsable)
+        .count();
+    let forced = match group.ty {
+        GroupType::SelectAll => true,
+        GroupType::SelectExactlyOne | GroupType::SelectAtLeastOne => usable <= 1,
+        _ => usable == 0,
+    };
+    if !forced {
+        match mode {
+            InstallMode::Prompt | InstallMode::Defaults => {}
+            InstallMode::Fail => {
+                return Err(crate::Error::InputRequired {
+                    mod_name: mod_name.into(),
+                    group: group.name.clone(),
+                })
+            }
+            InstallMode::Skip => return Ok(None),
+        }
+    }
+    if forced || mode == InstallMode::Defaults {
+        print_selection(group, &defaults);
+        return Ok(Some(defaults));
+    }
+    println!("{} ({})", group.name, group.ty.description());
+    for (i, plugin) in group.plugins.iter().enumerate() {
+        let marker = if defaults.contains(&i) { '*' } else { ' ' };
+        match types[i] {
+            PluginType::Required => println!(" {}{}. {} (required)", marker, i + 1, plugin.name),
+            PluginType::Recommended => {
+                println!(" {}{}. {} (recommended)", marker, i + 1, plugin.name)
+            }
+            PluginType::NotUsable => {
+                colorln!(dimmed, " {}{}. {} (not usable)", marker, i + 1, plugin.name)
+            }
+            PluginType::Optional | PluginType::CouldBeUsable => {
+                println!(" {}{}. {}", marker, i + 1, plugin.name)
+            }
+        }
+        for line in plugin
+            .description
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+        {
+            colorln!(dimmed, "       {}", line.trim());
+        }
+    }
+    loop {
+        let mut selected = utils::prompt_selection(count, &defaults)?;
+        if let Some(&i) = selected
+            .iter()
+            .find(|&&i| types[i] == PluginType::NotUsable)
+        {
+            colorln!(yellow, "{:?} is not usable", group.plugins[i].name);
+            continue;
+        }
+        // Required plugins cannot be deselected
+        for i in (0..count).filter(|&i| types[i] == PluginType::Required) {
+            if !selected.contains(&i) {
+                selected.push(i);
+            }
+        }
+        selected.sort_unstable();
+        if group.ty.allows(selected.len(), count) {
+            return Ok(Some(selected));
+        }
+        colorln!(
+            yellow,
+            "Invalid number of options, {}",
+            group.ty.description()
+        );
+    }
+}
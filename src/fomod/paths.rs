@@ -0,0 +1,55 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use pathdiff::diff_paths;
+use walkdir::WalkDir;
+
+/// Convert a path from a Fomod config, which may use backslashes, into a path
+pub fn normalize(raw: &str) -> PathBuf {
+    raw.split(['/', '\\'])
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect()
+}
+
+/// Get the key used to look up a path regardless of case
+pub(super) fn key(path: &Path) -> String {
+    path.iter()
+        .map(|part| part.to_string_lossy().to_lowercase())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Finds the real paths of Fomod sources in an extracted mod
+///
+/// Fomod configs are usually written on Windows, so their sources do not always match the
+/// case of the extracted files.
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    prefix: PathBuf,
+    index: HashMap<String, PathBuf>,
+}
+
+impl Resolver {
+    /// Index the files in the `prefix` folder of an extracted mod
+    pub fn new(extracted: &Path, prefix: &Path) -> Self {
+        let top = extracted.join(prefix);
+        let index = WalkDir::new(&top)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter_map(|entry| diff_paths(entry.path(), &top))
+            .map(|relative| (key(&relative), relative))
+            .collect();
+        Resolver {
+            prefix: prefix.to_path_buf(),
+            index,
+        }
+    }
+    /// Get the path of a source relative to the extracted mod, if it exists
+    pub fn resolve(&self, source: &Path) -> Option<PathBuf> {
+        self.index
+            .get(&key(source))
+            .map(|relative| self.prefix.join(relative))
+    }
+}
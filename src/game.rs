@@ -1,18 +1,27 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     fs::{self, File},
-    io::Write,
+    io::{self, Write},
     path::{Path, PathBuf},
-    process::Command,
+    sync::Mutex,
 };
 
 use indexmap::IndexMap;
 use itertools::Itertools;
 use pathdiff::diff_paths;
 use serde_derive::{Deserialize, Serialize};
-use walkdir::{DirEntry, WalkDir};
+use walkdir::WalkDir;
 
-use crate::{app::MoveSubcommand, colorln, fomod, library, utils, waitln};
+use crate::{
+    app::MoveSubcommand,
+    bain, colorln, extract, fomod,
+    layout::{CaseMap, CasePolicy, RootKind, RootRules},
+    library,
+    manifest::{DeployedFile, Manifest},
+    plan::{Action, FileChange, Plan},
+    progress::{Progress, Status},
+    utils,
+};
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct GlobalConfig {
@@ -54,10 +63,14 @@ impl GlobalConfig {
                 plugins_file: plugins,
                 exe,
                 deployment: DeploymentMethod::default(),
+                case_policy: CasePolicy::default(),
                 mods: IndexMap::new(),
                 curr_profile: None,
+                root_rules: RootRules::default(),
                 profiles: IndexMap::new(),
             },
+            manifest: Some(Manifest::default()),
+            discard: false,
         }
         .save()?;
         library::archives_dir(&name)?;
@@ -97,7 +110,56 @@ pub struct ManagedMod {
     pub enabled: bool,
     pub extracted: Option<PathBuf>,
     pub archive: PathBuf,
-    pub parts: Vec<PathBuf>,
+    /// Patterns of files that are not deployed
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hidden: Vec<String>,
+    /// Patterns of files that this mod provides regardless of load order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub preferred: Vec<String>,
+    /// The state of the archive when it was extracted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archive_stamp: Option<extract::ArchiveStamp>,
+    /// The files to install, which are decided when the mod is installed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<FileMapping>>,
+    /// Answers given to the mod's installer, which are reused when it runs again
+    #[serde(skip_serializing_if = "fomod::Answers::is_empty")]
+    pub answers: fomod::Answers,
+    /// The sub-packages chosen if the mod is a BAIN package
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bain: Option<bain::Selection>,
+    /// Metadata from the mod's Fomod info
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub info: Option<fomod::ModInfo>,
+}
+
+/// A file or folder in an extracted mod and where to install it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileMapping {
+    /// The path relative to the extracted folder
+    pub source: PathBuf,
+    /// The path relative to the data folder
+    pub destination: PathBuf,
+    #[serde(default)]
+    pub folder: bool,
+    /// Files with higher priority win when multiple entries install to the same path
+    #[serde(default)]
+    pub priority: i32,
+}
+
+impl FileMapping {
+    /// Install the contents of a folder to the root of the data folder
+    pub fn folder<P>(source: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        FileMapping {
+            source: source.into(),
+            destination: PathBuf::new(),
+            folder: true,
+            priority: 0,
+        }
+    }
 }
 
 impl ManagedMod {
@@ -107,28 +169,98 @@ impl ManagedMod {
             ..Self::default()
         }
     }
-    pub fn part_paths(&self) -> Vec<PathBuf> {
-        if self.parts.is_empty() {
-            if let Some(extr) = &self.extracted {
-                vec![extr.clone()]
-            } else {
-                Vec::new()
-            }
+    /// Check if the mod's archive has changed since it was extracted
+    pub fn archive_state(&self) -> crate::Result<extract::ArchiveState> {
+        if !self.archive.exists() {
+            return Ok(extract::ArchiveState::Missing);
+        }
+        Ok(match &self.archive_stamp {
+            Some(stamp) => stamp.check(&self.archive)?,
+            None => extract::ArchiveState::Unknown,
+        })
+    }
+    /// Get the mappings to install, which is the entire extracted folder if none are set
+    pub fn mappings(&self) -> Vec<FileMapping> {
+        self.files
+            .clone()
+            .unwrap_or_else(|| vec![FileMapping::folder("")])
+    }
+    /// Get the source path of every installed file and its path relative to the game folder
+    pub fn install_files(
+        &self,
+        data_folder: Option<&Path>,
+        rules: &RootRules,
+    ) -> crate::Result<IndexMap<PathBuf, PathBuf>> {
+        let mut files = IndexMap::new();
+        let extracted = if let Some(extracted) = &self.extracted {
+            extracted
         } else {
-            self.parts.clone()
+            return Ok(files);
+        };
+        let mut mappings = self.mappings();
+        // Later entries overwrite earlier ones, so sort by ascending priority
+        mappings.sort_by_key(|mapping| mapping.priority);
+        for mapping in mappings {
+            let source = extracted.join(&mapping.source);
+            if source.is_dir() {
+                // Folders whose entries belong in the game folder are installed there
+                let dest_dir = if mapping.destination.iter().count() == 0
+                    && rules.classify(&source, data_folder)? == Some(RootKind::Game)
+                {
+                    PathBuf::new()
+                } else {
+                    install_dir(Path::new(""), data_folder, false).join(&mapping.destination)
+                };
+                for entry in WalkDir::new(&source) {
+                    let entry = entry?;
+                    if entry.file_type().is_file() {
+                        let relative = diff_paths(entry.path(), &source).unwrap();
+                        files.insert(dest_dir.join(relative), entry.into_path());
+                    }
+                }
+            } else if source.is_file() {
+                let mut dest =
+                    install_dir(Path::new(""), data_folder, false).join(&mapping.destination);
+                if mapping.destination.iter().count() == 0 {
+                    dest = dest.join(source.file_name().unwrap());
+                }
+                files.insert(dest, source);
+            }
+        }
+        if !self.hidden.is_empty() {
+            files
+                .retain(|target, _| !matches_any(&self.hidden, &pattern_path(target, data_folder)));
         }
+        Ok(files)
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// How files are put in the game folder
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum DeploymentMethod {
+    #[default]
     Hardlink,
     Symlink,
+    Copy,
+    /// A copy-on-write clone, which falls back to a copy on filesystems that cannot clone
+    Reflink,
+    /// Hardlink files when the game and clim's folders are on the same device and copy them
+    /// otherwise
+    Auto,
 }
 
-impl Default for DeploymentMethod {
-    fn default() -> Self {
-        DeploymentMethod::Hardlink
+impl DeploymentMethod {
+    /// Check if a file deployed with a method is acceptable when deploying with this one
+    pub fn allows(self, used: DeploymentMethod) -> bool {
+        match self {
+            DeploymentMethod::Reflink => {
+                [DeploymentMethod::Reflink, DeploymentMethod::Copy].contains(&used)
+            }
+            DeploymentMethod::Auto => {
+                [DeploymentMethod::Hardlink, DeploymentMethod::Copy].contains(&used)
+            }
+            method => method == used,
+        }
     }
 }
 
@@ -140,7 +272,9 @@ pub struct Config {
     pub plugins_file: Option<PathBuf>,
     pub exe: Option<PathBuf>,
     pub deployment: DeploymentMethod,
+    pub case_policy: CasePolicy,
     pub curr_profile: Option<String>,
+    pub root_rules: RootRules,
     pub mods: IndexMap<String, ManagedMod>,
     #[serde(skip_serializing_if = "IndexMap::is_empty")]
     pub profiles: IndexMap<String, IndexMap<String, ManagedMod>>,
@@ -178,6 +312,10 @@ impl Config {
 pub struct Game {
     pub name: String,
     pub config: Config,
+    /// The files that are deployed, or `None` if they were deployed before clim kept track
+    pub manifest: Option<Manifest>,
+    /// Whether to skip saving when dropped
+    discard: bool,
 }
 
 const GAME_CONFIG_FILE: &str = "clim.toml";
@@ -196,11 +334,17 @@ impl Game {
         Ok(Game {
             name: name.into(),
             config,
+            manifest: Manifest::open(name)?,
+            discard: false,
         })
     }
     pub fn save(&self) -> crate::Result<()> {
         let string = toml::to_string_pretty(&self.config)?;
-        fs::write(self.config_file()?, &string).map_err(Into::into)
+        fs::write(self.config_file()?, &string)?;
+        if let Some(manifest) = &self.manifest {
+            manifest.save(&self.name)?;
+        }
+        Ok(())
     }
     pub fn get_mod(&mut self, name: &str) -> crate::Result<(&str, &mut ManagedMod)> {
         self.config.get_mod(name)
@@ -228,24 +372,79 @@ impl Game {
         Ok(())
     }
     fn enable_mod(
+        game_name: &str,
         data_folder: Option<&Path>,
+        rules: &RootRules,
+        env: &fomod::Environment,
+        options: &fomod::InstallOptions,
         mod_name: &str,
         mm: &mut ManagedMod,
     ) -> crate::Result<()> {
-        Game::extract_mod(mod_name, data_folder, mod_name, mm)?;
-        if !mm.enabled {
-            mm.enabled = true;
+        let was_enabled = mm.enabled;
+        mm.enabled = true;
+        let progress = Progress::new("Extracting", "Extracted", 1, true);
+        let extracted = Game::extract_mod(game_name, data_folder, rules, mod_name, mm, &progress);
+        progress.clear_line();
+        extracted?;
+        if !Game::install_mod(data_folder, rules, mod_name, mm, env, options)? {
+            mm.enabled = was_enabled;
+        } else if !was_enabled {
             println!("Enabled {}", mod_name);
         }
         Ok(())
     }
-    pub fn enable(&mut self, name: &str) -> crate::Result<()> {
+    pub fn enable(&mut self, name: &str, options: &fomod::InstallOptions) -> crate::Result<()> {
+        let env = self.fomod_env();
         let (mod_name, mm) = get_mod(&mut self.config.mods, name)?;
-        Game::enable_mod(self.config.data_folder.as_deref(), mod_name, mm)
+        Game::enable_mod(
+            &self.name,
+            self.config.data_folder.as_deref(),
+            &self.config.root_rules,
+            &env,
+            options,
+            mod_name,
+            mm,
+        )
     }
-    pub fn enable_all(&mut self) -> crate::Result<()> {
+    pub fn enable_all(
+        &mut self,
+        options: &fomod::InstallOptions,
+        jobs: usize,
+    ) -> crate::Result<()> {
+        let mut newly_enabled = HashSet::new();
         for (mod_name, mm) in &mut self.config.mods {
-            Game::enable_mod(self.config.data_folder.as_deref(), mod_name, mm)?;
+            if !mm.enabled {
+                mm.enabled = true;
+                newly_enabled.insert(mod_name.clone());
+            }
+        }
+        if let Err(e) = self.extract(jobs) {
+            for (mod_name, mm) in &mut self.config.mods {
+                if newly_enabled.contains(mod_name) && mm.extracted.is_none() {
+                    mm.enabled = false;
+                }
+            }
+            return Err(e);
+        }
+        // Conditions can depend on the files of the mods that were just extracted
+        let env = self.fomod_env();
+        // Installers can ask questions, so they run one at a time
+        for (mod_name, mm) in &mut self.config.mods {
+            let newly = newly_enabled.contains(mod_name);
+            if !Game::install_mod(
+                self.config.data_folder.as_deref(),
+                &self.config.root_rules,
+                mod_name,
+                mm,
+                &env,
+                options,
+            )? {
+                if newly {
+                    mm.enabled = false;
+                }
+            } else if newly {
+                println!("Enabled {}", mod_name);
+            }
         }
         Ok(())
     }
@@ -266,218 +465,652 @@ impl Game {
         }
         Ok(())
     }
-    fn extract(&mut self) -> crate::Result<()> {
+    /// Find extracted mods whose archives have changed and mark them to be extracted again
+    fn check_archives(&mut self) -> crate::Result<()> {
+        let mut changed = HashSet::new();
         for (mod_name, mm) in &mut self.config.mods {
-            Game::extract_mod(&self.name, self.config.data_folder.as_deref(), mod_name, mm)?;
+            if !mm.enabled || mm.extracted.is_none() {
+                continue;
+            }
+            match mm.archive_state()? {
+                extract::ArchiveState::Unchanged => {}
+                extract::ArchiveState::Changed => {
+                    colorln!(yellow, "The archive for {:?} has changed", mod_name);
+                    changed.insert(mod_name.clone());
+                }
+                extract::ArchiveState::Missing => colorln!(
+                    yellow,
+                    "The archive for {:?} is missing, so its extracted files will be used",
+                    mod_name
+                ),
+                // Assume the archive is the one that was extracted
+                extract::ArchiveState::Unknown => {
+                    mm.archive_stamp = Some(extract::ArchiveStamp::read(&mm.archive)?)
+                }
+            }
+        }
+        if changed.is_empty() {
+            return Ok(());
+        }
+        self.undeploy_mods(|mod_name| changed.contains(mod_name))?;
+        for mod_name in &changed {
+            self.config.mods[mod_name].extracted = None;
         }
         Ok(())
     }
+    /// Extract every enabled mod that has not been extracted, several at a time
+    fn extract(&mut self, jobs: usize) -> crate::Result<()> {
+        let game_name = &self.name;
+        let data_folder = self.config.data_folder.as_deref();
+        let rules = &self.config.root_rules;
+        let pending: Vec<(&str, &mut ManagedMod)> = self
+            .config
+            .mods
+            .iter_mut()
+            .filter(|(_, mm)| mm.enabled && mm.extracted.is_none())
+            .map(|(mod_name, mm)| (mod_name.as_str(), mm))
+            .collect();
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let progress = Progress::new("Extracting", "Extracted", pending.len(), true);
+        let result = utils::parallel(pending, jobs, |(mod_name, mm)| {
+            Game::extract_mod(game_name, data_folder, rules, mod_name, mm, &progress)
+        });
+        progress.done();
+        result
+    }
     fn extract_mod(
         game_name: &str,
         data_folder: Option<&Path>,
+        rules: &RootRules,
         mod_name: &str,
         mm: &mut ManagedMod,
+        progress: &Progress,
     ) -> crate::Result<()> {
         if mm.enabled && mm.extracted.is_none() {
-            waitln!("Extracting {:?}...", mod_name);
             let extracted_dir = library::extracted_dir(game_name, mod_name)?;
             let _ = fs::remove_dir_all(&extracted_dir);
-            // Extract
-            let status = Command::new("7z")
-                .arg("x")
-                .arg(&mm.archive)
-                .arg(format!("-o{}", extracted_dir.to_string_lossy()))
-                .arg("-spe")
-                .output()?
-                .status;
-            if !status.success() {
-                return Err(crate::Error::Extraction {
-                    archive: mm.archive.clone(),
-                    code: status.code(),
-                });
-            }
-            // If there is exactly one entry in the folder and it is not a Data folder
-            if fs::read_dir(&extracted_dir)?
-                .filter_map(Result::ok)
-                .filter(|entry| entry.path().is_dir())
-                .count()
-                == 1
-                && !contains_data_folder(&extracted_dir, data_folder)?
-            {
-                // Get the inner folder
-                let narrowed = fs::read_dir(&extracted_dir)?
-                    .filter_map(Result::ok)
-                    .find(|entry| entry.path().is_dir())
-                    .unwrap()
-                    .path();
-                // Rename all entries in the inner folder to be in the outer folder
-                for entry in fs::read_dir(&narrowed)?.filter_map(Result::ok) {
-                    let path_diff = diff_paths(entry.path(), &narrowed).unwrap();
-                    let new_path = extracted_dir.join(path_diff);
-                    fs::rename(entry.path(), new_path)?;
+            progress.update(mod_name, Status::default());
+            extract::extract_archive(&mm.archive, &extracted_dir, &mut |status| {
+                if status.files.is_multiple_of(50) || status.files == status.total_files {
+                    progress.update(mod_name, status);
                 }
-                // Remove the now-empty inner folder
-                fs::remove_dir(narrowed)?;
-            }
-            // Capitalize all folders on unix
-            if cfg!(unix) {
-                for entry in WalkDir::new(&extracted_dir)
-                    .into_iter()
-                    .filter_map(Result::ok)
-                {
-                    if entry.file_type().is_dir() {
-                        let _ = fs::rename(
-                            entry.path(),
-                            utils::capitalize_path(&extracted_dir, entry.path()),
-                        );
-                    }
+            })?;
+            // Move the real root of the mod out of any wrapper folders
+            let (root, _) = rules.find_root(&extracted_dir, data_folder)?;
+            if let Some(wrapper) = root.iter().next() {
+                let temp = extracted_dir.join(".clim-root");
+                fs::rename(extracted_dir.join(&root), &temp)?;
+                // The outer wrappers held nothing but the root
+                if root.iter().count() > 1 {
+                    fs::remove_dir_all(extracted_dir.join(wrapper))?;
                 }
+                for entry in fs::read_dir(&temp)?.filter_map(Result::ok) {
+                    fs::rename(entry.path(), extracted_dir.join(entry.file_name()))?;
+                }
+                fs::remove_dir(temp)?;
             }
+            // Run the installer again in case the contents changed, reusing previous answers
+            mm.files = None;
+            mm.info = fomod::ModInfo::find(&extracted_dir).unwrap_or_else(|e| {
+                progress.message(&format!("Unable to read info for {:?}: {}", mod_name, e));
+                None
+            });
+            mm.archive_stamp = Some(extract::ArchiveStamp::read(&mm.archive)?);
             mm.extracted = Some(extracted_dir);
-            colorln!(green, "done");
+            progress.finish(mod_name);
         }
         Ok(())
     }
-    fn undeploy_mod(
-        game_folder: &Path,
-        data_folder: Option<&Path>,
-        mm: &mut ManagedMod,
-    ) -> crate::Result<()> {
-        for install_src in mm.part_paths() {
-            let contains_data_folder = match contains_data_folder(&install_src, data_folder) {
-                Ok(cdf) => cdf,
-                Err(_) => continue,
-            };
-            let install_dir = install_dir(&game_folder, data_folder, contains_data_folder);
-            let src_diff = differ(&install_src);
-            for entry in WalkDir::new(&install_src) {
-                let file_entry = entry?;
-                utils::remove_path(&install_dir, src_diff(&file_entry.path()).unwrap())?;
+    /// Remove the deployed files of the mods that match a predicate
+    fn undeploy_mods<F>(&mut self, mut remove: F) -> crate::Result<()>
+    where
+        F: FnMut(&str) -> bool,
+    {
+        let game_folder = &self.config.game_folder;
+        if let Some(manifest) = &mut self.manifest {
+            let backup_dir = library::backup_dir(&self.name)?;
+            return manifest.undeploy(game_folder, &backup_dir, |file| remove(&file.mod_name));
+        }
+        // Without a manifest, the files have to be worked out from the extracted folders
+        let mut case_map = CaseMap::new(game_folder, self.config.case_policy);
+        for (mod_name, mm) in &self.config.mods {
+            if !remove(mod_name) {
+                continue;
+            }
+            let data_folder = self.config.data_folder.as_deref();
+            for target in mm
+                .install_files(data_folder, &self.config.root_rules)?
+                .keys()
+            {
+                utils::remove_path(game_folder, case_map.resolve(target))?;
             }
         }
         Ok(())
     }
     fn undeploy(&mut self) -> crate::Result<()> {
-        for (_, mm) in &mut self.config.mods {
-            Game::undeploy_mod(
+        self.undeploy_mods(|_| true)
+    }
+    /// Get the environment that Fomod conditions are checked against
+    fn fomod_env(&self) -> fomod::Environment {
+        let mod_files = self
+            .config
+            .mods
+            .values()
+            .filter(|mm| mm.enabled)
+            .flat_map(|mm| {
+                mm.install_files(self.config.data_folder.as_deref(), &self.config.root_rules)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(target, _)| target)
+            });
+        fomod::Environment::new(
+            self.config.game_folder.clone(),
+            install_dir(
                 &self.config.game_folder,
                 self.config.data_folder.as_deref(),
-                mm,
-            )?;
+                false,
+            ),
+            mod_files,
+        )
+    }
+    /// Run a mod's installer if it has one and has not been installed yet
+    ///
+    /// Returns whether the mod is ready to be deployed
+    fn install_mod(
+        data_folder: Option<&Path>,
+        rules: &RootRules,
+        mod_name: &str,
+        mm: &mut ManagedMod,
+        env: &fomod::Environment,
+        options: &fomod::InstallOptions,
+    ) -> crate::Result<bool> {
+        let extracted_dir = if let Some(extracted_dir) = &mm.extracted {
+            extracted_dir
+        } else {
+            return Ok(false);
+        };
+        let found = fomod::Fomod::find(extracted_dir);
+        // Requirements are checked every time since other mods may have changed
+        if let Ok(Some((_, config))) = &found {
+            fomod::check_dependencies(mod_name, config, env, options)?;
         }
-        Ok(())
+        if mm.files.is_some() {
+            return Ok(true);
+        }
+        mm.files = Some(match found {
+            Ok(Some((root, config))) => {
+                let resolver = fomod::Resolver::new(extracted_dir, &root);
+                match fomod::install(mod_name, &resolver, &config, env, options, &mm.answers)? {
+                    Some((files, answers)) => {
+                        mm.answers = answers;
+                        files
+                    }
+                    None => return Ok(false),
+                }
+            }
+            Ok(None) => match bain::Package::find(extracted_dir)? {
+                Some(package) => match bain::install(
+                    mod_name,
+                    extracted_dir,
+                    &package,
+                    env,
+                    options,
+                    mm.bain.as_ref(),
+                )? {
+                    Some((files, selection)) => {
+                        mm.bain = Some(selection);
+                        files
+                    }
+                    None => return Ok(false),
+                },
+                None => {
+                    if rules.classify(extracted_dir, data_folder)?.is_none() {
+                        colorln!(
+                            yellow,
+                            "Unable to tell where the files of {:?} belong, \
+                            so they will be installed to the data folder",
+                            mod_name
+                        );
+                    }
+                    vec![FileMapping::folder("")]
+                }
+            },
+            Err(e) => match options.mode {
+                fomod::InstallMode::Prompt => {
+                    colorln!(yellow, "Unable to read installer for {:?}: {}", mod_name, e);
+                    fomod::pseudo_fomod(mod_name, extracted_dir, data_folder, rules)?
+                }
+                // There are no defaults to take from an installer that cannot be read
+                fomod::InstallMode::Defaults | fomod::InstallMode::Skip => {
+                    colorln!(yellow, "Skipped {:?}: {}", mod_name, e);
+                    return Ok(false);
+                }
+                fomod::InstallMode::Fail => return Err(e),
+            },
+        });
+        Ok(true)
     }
-    fn deploy(&mut self) -> crate::Result<()> {
+    /// Run the installers of enabled mods, returning the mods that are ready to be deployed
+    fn install(&mut self, options: &fomod::InstallOptions) -> crate::Result<Vec<String>> {
+        let env = self.fomod_env();
+        // Installers can ask questions, so they run one at a time in load order
+        let mut ready = Vec::new();
         for (mod_name, mm) in &mut self.config.mods {
-            if let (Some(extracted_dir), true) = (&mm.extracted, mm.enabled) {
-                // Search for a Fomod config
-                let config = WalkDir::new(&extracted_dir)
-                    .into_iter()
-                    .filter_map(Result::ok)
-                    .find(|entry| {
-                        entry
-                            .path()
-                            .file_name()
-                            .map_or(false, |name| name == "ModuleConfig.xml")
-                    })
-                    .map(DirEntry::into_path);
-                // Get a list of folders from which to install things
-                let install_folders = if !mm.parts.is_empty() {
-                    mm.parts.clone()
-                } else if config.is_some() {
-                    let paths = fomod::pseudo_fomod(mod_name, &extracted_dir)?;
-                    mm.parts = paths.clone();
-                    paths
+            if mm.enabled
+                && Game::install_mod(
+                    self.config.data_folder.as_deref(),
+                    &self.config.root_rules,
+                    mod_name,
+                    mm,
+                    &env,
+                    options,
+                )?
+            {
+                ready.push(mod_name.clone());
+            }
+        }
+        Ok(ready)
+    }
+    /// Work out how the game folder has to change to deploy the files of the ready mods
+    ///
+    /// Nothing is changed. The deployed files of pending mods are left out, since what they
+    /// should be is not known until the mods are installed.
+    fn plan(&self, ready: &[String], pending: &[String]) -> crate::Result<Plan> {
+        // Decide which mod provides each file before linking anything, so the result does
+        // not depend on the order that mods finish in
+        let mut wanted = IndexMap::new();
+        for (target, mut sources) in self.providers(ready)? {
+            let winner = self.winner(&target, &sources);
+            let (mod_name, source) = sources.swap_remove(winner);
+            let others: Vec<PathBuf> = sources.into_iter().map(|(_, source)| source).collect();
+            wanted.insert(target, (mod_name, source, others));
+        }
+        let game_folder = &self.config.game_folder;
+        // The backup folder is only created once something is deployed
+        let backup_dir = library::game_dir(&self.name)?.join("backup");
+        let deployment = self.config.deployment;
+        let mut plan = Plan::default();
+        // Keep the files that are already deployed correctly and replace or remove the rest
+        let mut replaced = HashMap::new();
+        for file in self.manifest.iter().flat_map(|manifest| &manifest.files) {
+            if pending.contains(&file.mod_name) {
+                continue;
+            }
+            let keep = wanted
+                .get(&file.target)
+                .is_some_and(|(mod_name, source, _)| {
+                    &file.mod_name == mod_name
+                        && &file.source == source
+                        && deployment.allows(file.method)
+                        && file.is_unchanged(game_folder)
+                        && (file.method != DeploymentMethod::Hardlink
+                            || utils::same_file(source, game_folder.join(&file.target)))
+                });
+            if keep {
+                wanted.swap_remove(&file.target);
+                plan.unchanged += 1;
+                continue;
+            }
+            plan.outdated.push(file.target.clone());
+            if wanted.contains_key(&file.target) {
+                replaced.insert(&file.target, file);
+            } else {
+                plan.files.push(FileChange {
+                    action: Action::Remove,
+                    target: file.target.clone(),
+                    mod_name: file.mod_name.clone(),
+                    source: None,
+                    backup: false,
+                    overwritten: Vec::new(),
+                });
+            }
+        }
+        // Predict what deploy_file finds once the outdated files are removed
+        for (target, (mod_name, source, others)) in wanted {
+            let mut change = FileChange {
+                action: Action::Create,
+                target,
+                mod_name,
+                source: None,
+                backup: false,
+                overwritten: Vec::new(),
+            };
+            let path = game_folder.join(&change.target);
+            if let Ok(meta) = fs::symlink_metadata(&path) {
+                change.action = Action::Replace;
+                let old = replaced
+                    .get(&change.target)
+                    .filter(|file| file.is_unchanged(game_folder));
+                let error = if let Some(old) = old {
+                    // Removing the old file puts back any file that it replaced
+                    change.backup = old.backed_up;
+                    None
+                } else if utils::same_file(&source, &path)
+                    || others.iter().any(|other| utils::same_file(other, &path))
+                {
+                    None
+                } else if meta.is_dir() {
+                    Some("a folder is in the way".to_string())
+                } else if fs::symlink_metadata(backup_dir.join(&change.target)).is_ok() {
+                    Some(format!(
+                        "a file is in the way and {:?} already exists",
+                        backup_dir.join(&change.target)
+                    ))
                 } else {
-                    vec![extracted_dir.clone()]
+                    change.backup = true;
+                    None
                 };
-                // For each folder
-                for install_src in install_folders {
-                    let contains_data_folder =
-                        contains_data_folder(&install_src, self.config.data_folder.as_deref())?;
-                    let install_dir = install_dir(
-                        &self.config.game_folder,
-                        self.config.data_folder.as_deref(),
-                        contains_data_folder,
-                    );
-                    let src_diff = differ(&install_src);
-                    // For each file
-                    for entry in WalkDir::new(&install_src) {
-                        let file_entry = entry?;
-                        if file_entry.file_type().is_file() {
-                            let extracted_path =
-                                install_src.join(src_diff(&file_entry.path()).unwrap());
-                            let install_path =
-                                install_dir.join(src_diff(&file_entry.path()).unwrap());
-                            utils::create_dirs(&install_path)?;
-                            // Deploy
-                            match self.config.deployment {
-                                DeploymentMethod::Hardlink => {
-                                    let _ = fs::hard_link(extracted_path, install_path);
-                                }
-                                DeploymentMethod::Symlink => {
-                                    #[cfg(unix)]
-                                    let _ =
-                                        std::os::unix::fs::symlink(extracted_path, install_path);
-                                    #[cfg(windows)]
-                                    let _ = std::os::windows::fs::hardlink(
-                                        extracted_path,
-                                        install_path,
-                                    );
-                                }
+                if let Some(error) = error {
+                    plan.errors.push(format!(
+                        "{} ({}): {}",
+                        change.target.to_string_lossy(),
+                        change.mod_name,
+                        error
+                    ));
+                    continue;
+                }
+            }
+            change.source = Some(source);
+            change.overwritten = others;
+            plan.files.push(change);
+        }
+        plan.files.sort_by(|a, b| a.target.cmp(&b.target));
+        Ok(plan)
+    }
+    /// Deploy the files of enabled mods as planned
+    ///
+    /// Only files that differ from the last deployment are removed, added or replaced.
+    fn deploy(&mut self, plan: Plan, jobs: usize) -> crate::Result<()> {
+        let game_folder = &self.config.game_folder;
+        let backup_dir = &library::backup_dir(&self.name)?;
+        let deployment = self.config.deployment;
+        let outdated: HashSet<&PathBuf> = plan.outdated.iter().collect();
+        self.manifest
+            .get_or_insert_with(Manifest::default)
+            .undeploy(game_folder, backup_dir, |file| {
+                outdated.contains(&file.target)
+            })?;
+        let mut removed = 0;
+        let mut work: IndexMap<String, Vec<_>> = IndexMap::new();
+        for change in plan.files {
+            match change.source {
+                Some(source) => work.entry(change.mod_name).or_default().push((
+                    change.target,
+                    source,
+                    change.overwritten,
+                )),
+                None => removed += 1,
+            }
+        }
+        let mut failed = plan.errors;
+        if work.is_empty() && failed.is_empty() {
+            if removed == 0 {
+                colorln!(green, "Everything is already deployed");
+            } else {
+                colorln!(green, "Removed {} files", removed);
+            }
+            return Ok(());
+        }
+        let work: Vec<_> = work.into_iter().collect();
+        let deployed = Mutex::new(Vec::new());
+        let failures = Mutex::new(Vec::new());
+        let progress = Progress::new("Deploying", "Deployed", work.len(), false);
+        let result = utils::parallel(work, jobs, |(mod_name, files)| {
+            let mod_name = mod_name.as_str();
+            let mut status = Status {
+                total_files: files.len(),
+                ..Status::default()
+            };
+            for (target, source, others) in files {
+                let install_path = game_folder.join(&target);
+                status.bytes += fs::metadata(&source).map_or(0, |meta| meta.len());
+                let backup = backup_dir.join(&target);
+                let file = deploy_file(deployment, &source, &install_path, &others, &backup)
+                    .and_then(|(method, backed_up)| {
+                        DeployedFile::new(
+                            game_folder,
+                            target.clone(),
+                            mod_name,
+                            source,
+                            method,
+                            backed_up,
+                        )
+                        .or_else(|e| {
+                            // Undo the deployment so the game folder matches the manifest
+                            fs::remove_file(&install_path)?;
+                            if backed_up {
+                                utils::move_file(&backup, &install_path)?;
                             }
-                        }
-                    }
+                            Err(e)
+                        })
+                    });
+                match file {
+                    Ok(file) => deployed.lock().unwrap().push(file),
+                    Err(e) => failures.lock().unwrap().push(format!(
+                        "{} ({}): {}",
+                        target.to_string_lossy(),
+                        mod_name,
+                        e
+                    )),
+                }
+                status.files += 1;
+                if status.files.is_multiple_of(50) || status.files == status.total_files {
+                    progress.update(mod_name, status);
                 }
             }
+            progress.finish(mod_name);
+            Ok(())
+        });
+        progress.done();
+        let manifest = self.manifest.get_or_insert_with(Manifest::default);
+        manifest.files.extend(deployed.into_inner().unwrap());
+        manifest.files.sort_by(|a, b| a.target.cmp(&b.target));
+        result?;
+        failed.extend(failures.into_inner().unwrap());
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            failed.sort();
+            Err(crate::Error::DeployFailed(failed))
+        }
+    }
+    /// Get the mods that provide each file, in load order, by where the file is deployed
+    fn providers(&self, mods: &[String]) -> crate::Result<Providers> {
+        let mut providers = Providers::new();
+        let mut case_map = CaseMap::new(&self.config.game_folder, self.config.case_policy);
+        for mod_name in mods {
+            for (target, source) in self.config.mods[mod_name]
+                .install_files(self.config.data_folder.as_deref(), &self.config.root_rules)?
+            {
+                providers
+                    .entry(case_map.resolve(&target))
+                    .or_default()
+                    .push((mod_name.clone(), source));
+            }
+        }
+        Ok(providers)
+    }
+    /// Get the index of the mod whose file is deployed
+    ///
+    /// Mods later in the load order overwrite earlier ones, unless a mod prefers the file.
+    fn winner(&self, target: &Path, providers: &[(String, PathBuf)]) -> usize {
+        let path = pattern_path(target, self.config.data_folder.as_deref());
+        providers
+            .iter()
+            .rposition(|(mod_name, _)| matches_any(&self.config.mods[mod_name].preferred, &path))
+            .unwrap_or(providers.len() - 1)
+    }
+    /// Hide files in a mod, or show them again
+    pub fn hide(&mut self, name: &str, patterns: Vec<String>, remove: bool) -> crate::Result<()> {
+        let (mod_name, mm) = get_mod(&mut self.config.mods, name)?;
+        if patterns.is_empty() {
+            for pattern in &mm.hidden {
+                println!("{}", pattern);
+            }
+            return Ok(());
+        }
+        for pattern in update_patterns(&mut mm.hidden, patterns, remove) {
+            if remove {
+                println!("No longer hiding {:?} in {}", pattern, mod_name);
+            } else {
+                println!("Hiding {:?} in {}", pattern, mod_name);
+            }
         }
         Ok(())
     }
+    /// Make a mod provide files regardless of load order, or stop it from doing so
+    pub fn prefer(&mut self, name: &str, patterns: Vec<String>, remove: bool) -> crate::Result<()> {
+        let (mod_name, mm) = get_mod(&mut self.config.mods, name)?;
+        if patterns.is_empty() {
+            for pattern in &mm.preferred {
+                println!("{}", pattern);
+            }
+            return Ok(());
+        }
+        for pattern in update_patterns(&mut mm.preferred, patterns, remove) {
+            if remove {
+                println!("{} no longer wins {:?}", mod_name, pattern);
+            } else {
+                println!("{} now wins {:?}", mod_name, pattern);
+            }
+        }
+        Ok(())
+    }
+    /// Find the files that more than one installed mod provides
+    ///
+    /// Files that are the same in every mod are not conflicts.
+    pub fn conflicts(&self) -> crate::Result<Vec<Conflict>> {
+        let installed: Vec<String> = self
+            .config
+            .mods
+            .iter()
+            .filter(|(_, mm)| mm.enabled && mm.extracted.is_some() && mm.files.is_some())
+            .map(|(mod_name, _)| mod_name.clone())
+            .collect();
+        let mut conflicts = Vec::new();
+        for (target, sources) in self.providers(&installed)? {
+            if sources.len() < 2 {
+                continue;
+            }
+            let winner = self.winner(&target, &sources);
+            let winning_source = &sources[winner].1;
+            let mut identical = true;
+            for (_, source) in &sources {
+                if !utils::same_contents(source, winning_source)? {
+                    identical = false;
+                    break;
+                }
+            }
+            if identical {
+                continue;
+            }
+            conflicts.push(Conflict {
+                target,
+                mods: sources.into_iter().map(|(mod_name, _)| mod_name).collect(),
+                winner,
+            });
+        }
+        Ok(conflicts)
+    }
     pub fn plugins(&self) -> impl Iterator<Item = PathBuf> + '_ {
         self.config
             .mods
             .values()
             .filter(|mm| mm.enabled)
-            .flat_map(|mm| mm.part_paths())
-            .flat_map(|path| WalkDir::new(path).into_iter().filter_map(Result::ok))
-            .filter_map(|entry| {
-                entry.path().extension().and_then(|ext| {
-                    if ["esp", "esm", "esl"].contains(&ext.to_string_lossy().as_ref()) {
-                        Some(entry.path().file_name().unwrap().into())
-                    } else {
-                        None
-                    }
-                })
+            .flat_map(move |mm| {
+                mm.install_files(self.config.data_folder.as_deref(), &self.config.root_rules)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(target, _)| target)
             })
+            .filter(|target| utils::is_plugin(target))
+            .filter_map(|target| target.file_name().map(Into::into))
             .dedup()
     }
+    /// Get the lines of the plugins file
+    fn plugin_lines(&self) -> Vec<String> {
+        self.plugins()
+            .map(|plugin| format!("*{}", plugin.to_string_lossy()))
+            .collect()
+    }
     pub fn write_plugins(&self) -> crate::Result<()> {
         if let Some(plugins) = &self.config.plugins_file {
             let mut file = File::create(plugins)?;
-            for plugin in self.plugins() {
-                writeln!(file, "*{}", plugin.to_string_lossy())?;
+            for line in self.plugin_lines() {
+                writeln!(file, "{}", line)?;
             }
         }
         Ok(())
     }
-    pub fn go(&mut self) -> crate::Result<()> {
-        self.extract()?;
-        waitln!("Deploying...");
-        self.undeploy()?;
-        self.deploy()?;
+    pub fn go(&mut self, options: &fomod::InstallOptions, jobs: usize) -> crate::Result<()> {
+        self.check_archives()?;
+        self.extract(jobs)?;
+        if self.manifest.is_none() {
+            self.undeploy()?;
+            self.manifest = Some(Manifest::default());
+        }
+        let ready = self.install(options)?;
+        let plan = self.plan(&ready, &[])?;
+        // Files that were deployed still need their plugins listed
+        let deployed = self.deploy(plan, jobs);
         self.write_plugins()?;
-        colorln!(green, "done");
-        Ok(())
+        deployed
+    }
+    /// Work out what `go` would do without changing anything
+    ///
+    /// Mods that have to be extracted or installed first are listed, but their files are not
+    /// known until their installers run.
+    pub fn dry_run(&self) -> crate::Result<Plan> {
+        let mut extract = Vec::new();
+        let mut install = Vec::new();
+        let mut ready = Vec::new();
+        for (mod_name, mm) in &self.config.mods {
+            if !mm.enabled {
+                continue;
+            }
+            let stale =
+                mm.extracted.is_none() || mm.archive_state()? == extract::ArchiveState::Changed;
+            if stale {
+                extract.push(mod_name.clone());
+                install.push(mod_name.clone());
+            } else if mm.files.is_none() {
+                install.push(mod_name.clone());
+            } else {
+                ready.push(mod_name.clone());
+            }
+        }
+        let mut plan = self.plan(&ready, &install)?;
+        plan.extract = extract;
+        plan.install = install;
+        if let Some(plugins) = &self.config.plugins_file {
+            let old: Vec<String> = fs::read_to_string(plugins)
+                .unwrap_or_default()
+                .lines()
+                .map(Into::into)
+                .collect();
+            let new = self.plugin_lines();
+            plan.plugins_added = new
+                .iter()
+                .filter(|line| !old.contains(line))
+                .cloned()
+                .collect();
+            plan.plugins_removed = old.into_iter().filter(|line| !new.contains(line)).collect();
+        }
+        plan.conflicts = self.conflicts()?;
+        Ok(plan)
+    }
+    /// Keep the config and manifest from being saved when the game is dropped
+    pub fn discard_changes(&mut self) {
+        self.discard = true;
     }
     fn uninstall_mod(
-        game_folder: &Path,
-        data_folder: Option<&Path>,
         mod_name: &str,
         mm: &mut ManagedMod,
         delete_archives: bool,
     ) -> crate::Result<()> {
         Game::disable_mod(mod_name, mm);
-        Game::undeploy_mod(game_folder, data_folder, mm)?;
         if delete_archives {
             fs::remove_file(&mm.archive)?;
         }
@@ -488,29 +1121,18 @@ impl Game {
         Ok(())
     }
     pub fn uninstall(&mut self, name: &str, delete_archives: bool) -> crate::Result<()> {
-        let (mod_name, mm) = get_mod(&mut self.config.mods, name)?;
-        Game::uninstall_mod(
-            &self.config.game_folder,
-            self.config.data_folder.as_deref(),
-            mod_name,
-            mm,
-            delete_archives,
-        )?;
+        let mod_name = get_mod(&mut self.config.mods, name)?.0.to_string();
+        self.undeploy_mods(|other| other == mod_name)?;
+        Game::uninstall_mod(&mod_name, &mut self.config.mods[&mod_name], delete_archives)?;
         if delete_archives {
-            let mod_name = mod_name.to_string();
-            self.config.mods.remove(&mod_name);
+            self.config.mods.shift_remove(&mod_name);
         }
         Ok(())
     }
     pub fn uninstall_all(&mut self, delete_archives: bool) -> crate::Result<()> {
+        self.undeploy()?;
         for (mod_name, mm) in &mut self.config.mods {
-            Game::uninstall_mod(
-                &self.config.game_folder,
-                self.config.data_folder.as_deref(),
-                mod_name,
-                mm,
-                delete_archives,
-            )?;
+            Game::uninstall_mod(mod_name, mm, delete_archives)?;
         }
         if delete_archives {
             self.config.mods.clear();
@@ -626,7 +1248,7 @@ impl Game {
                 *mm = profile_mm.clone();
             } else {
                 mm.enabled = !disable_new;
-                mm.parts.clear();
+                mm.files = None;
             }
         }
         println!("Loaded profile {:?}", profile_name);
@@ -637,25 +1259,154 @@ impl Game {
 
 impl Drop for Game {
     fn drop(&mut self) {
+        if self.discard {
+            return;
+        }
         if let Err(e) = self.save() {
             println!("Error saving config: {}", e);
         }
     }
 }
 
-fn differ<P>(top: &P) -> impl Fn(&'_ Path) -> Option<PathBuf> + '_
-where
-    P: AsRef<Path>,
-{
-    move |path| diff_paths(path, top)
+/// The sources of each file by where it is deployed, with the providing mods in load order
+type Providers = IndexMap<PathBuf, Vec<(String, PathBuf)>>;
+
+/// Get the path that patterns are matched against
+///
+/// Paths in the data folder are relative to it, and `/` separates folders.
+fn pattern_path(target: &Path, data_folder: Option<&Path>) -> String {
+    let mut parts = target.iter().map(|part| part.to_string_lossy()).peekable();
+    if let Some(data) = data_folder {
+        for data_part in data.iter() {
+            match parts.peek() {
+                Some(part) if part.eq_ignore_ascii_case(&data_part.to_string_lossy()) => {
+                    parts.next();
+                }
+                _ => return target.iter().map(|part| part.to_string_lossy()).join("/"),
+            }
+        }
+    }
+    parts.join("/")
 }
 
-fn contains_data_folder(path: &Path, data_folder: Option<&Path>) -> crate::Result<bool> {
-    Ok(if let Some(data) = data_folder {
-        fs::read_dir(&path)?
-            .filter_map(Result::ok)
-            .any(|entry| entry.path().ends_with(data))
-    } else {
-        false
-    })
+fn matches_any(patterns: &[String], path: &str) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| utils::matches_pattern(pattern, path))
+}
+
+/// Add patterns to a list or remove them from it, returning the ones that changed it
+fn update_patterns(list: &mut Vec<String>, patterns: Vec<String>, remove: bool) -> Vec<String> {
+    let mut changed = Vec::new();
+    for pattern in patterns {
+        let pattern = pattern.replace('\\', "/");
+        let position = list.iter().position(|existing| existing == &pattern);
+        match (position, remove) {
+            (None, false) => list.push(pattern.clone()),
+            (Some(i), true) => {
+                list.remove(i);
+            }
+            _ => continue,
+        }
+        changed.push(pattern);
+    }
+    changed
+}
+
+/// A file that more than one mod provides
+#[derive(Debug, Clone, Serialize)]
+pub struct Conflict {
+    /// The path relative to the game folder
+    pub target: PathBuf,
+    /// The mods that provide the file, in load order
+    pub mods: Vec<String>,
+    /// The index of the mod whose file is deployed
+    pub winner: usize,
+}
+
+impl Conflict {
+    pub fn winner(&self) -> &str {
+        &self.mods[self.winner]
+    }
+}
+
+/// Put a file in the game folder, returning the method that was used and whether an existing
+/// file was backed up
+///
+/// An existing link to this file or to one of the files it overwrites is replaced. Any other
+/// existing file is moved to the backup path so that it can be put back when the file is
+/// undeployed.
+fn deploy_file(
+    deployment: DeploymentMethod,
+    source: &Path,
+    target: &Path,
+    overwritten: &[PathBuf],
+    backup: &Path,
+) -> io::Result<(DeploymentMethod, bool)> {
+    let mut backed_up = false;
+    if let Ok(meta) = fs::symlink_metadata(target) {
+        if utils::same_file(source, target)
+            || overwritten
+                .iter()
+                .any(|other| utils::same_file(other, target))
+        {
+            fs::remove_file(target)?;
+        } else if meta.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "a folder is in the way",
+            ));
+        } else if fs::symlink_metadata(backup).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("a file is in the way and {:?} already exists", backup),
+            ));
+        } else {
+            utils::move_file(target, backup)?;
+            backed_up = true;
+        }
+    }
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let linked = link_file(deployment, source, target);
+    if linked.is_err() && backed_up {
+        utils::move_file(backup, target)?;
+    }
+    linked.map(|method| (method, backed_up))
+}
+
+/// Put a file in the game folder, returning the method that was used
+fn link_file(
+    deployment: DeploymentMethod,
+    source: &Path,
+    target: &Path,
+) -> io::Result<DeploymentMethod> {
+    match deployment {
+        DeploymentMethod::Hardlink => fs::hard_link(source, target)?,
+        DeploymentMethod::Symlink => {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(source, target)?;
+            #[cfg(windows)]
+            fs::hard_link(source, target)?;
+        }
+        DeploymentMethod::Copy => {
+            fs::copy(source, target)?;
+        }
+        DeploymentMethod::Reflink => {
+            if utils::reflink(source, target).is_err() {
+                return link_file(DeploymentMethod::Copy, source, target);
+            }
+        }
+        DeploymentMethod::Auto => {
+            let same_device = target
+                .parent()
+                .is_some_and(|parent| utils::same_device(source, parent));
+            if same_device && fs::hard_link(source, target).is_ok() {
+                return Ok(DeploymentMethod::Hardlink);
+            }
+            return link_file(DeploymentMethod::Copy, source, target);
+        }
+    }
+    Ok(deployment)
 }
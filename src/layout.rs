@@ -0,0 +1,205 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::utils;
+
+/// Where the files at the top of a mod belong
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RootKind {
+    /// The top of the mod is the data folder
+    Data,
+    /// The top of the mod is the game folder
+    Game,
+}
+
+/// Rules for finding the real root of an extracted mod
+///
+/// Patterns match the names of top-level entries, ignoring case, and may use `*` and `?`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RootRules {
+    /// Entries that belong at the top of the data folder
+    pub data_entries: Vec<String>,
+    /// Entries that belong at the top of the game folder
+    pub root_entries: Vec<String>,
+}
+
+impl Default for RootRules {
+    fn default() -> Self {
+        let strings = |names: &[&str]| names.iter().map(|&name| name.into()).collect();
+        RootRules {
+            data_entries: strings(&[
+                "meshes",
+                "textures",
+                "scripts",
+                "interface",
+                "sound",
+                "music",
+                "strings",
+                "materials",
+                "seq",
+                "lodsettings",
+                "grass",
+                "video",
+                "shadersfx",
+                "skse",
+                "f4se",
+                "nvse",
+                "fose",
+                "obse",
+                "*.esp",
+                "*.esm",
+                "*.esl",
+                "*.bsa",
+                "*.ba2",
+            ]),
+            root_entries: strings(&[
+                "*.dll",
+                "*.exe",
+                "enbseries",
+                "enblocal.ini",
+                "enbseries.ini",
+                "reshade-shaders",
+            ]),
+        }
+    }
+}
+
+impl RootRules {
+    /// Decide where the entries of a folder belong, if the rules recognize any of them
+    ///
+    /// A folder that contains the data folder belongs in the game folder.
+    pub fn classify(&self, dir: &Path, data_folder: Option<&Path>) -> io::Result<Option<RootKind>> {
+        if !dir.is_dir() {
+            return Ok(None);
+        }
+        let names: Vec<String> = fs::read_dir(dir)?
+            .filter_map(Result::ok)
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+        let matches = |patterns: &[String]| {
+            names.iter().any(|name| {
+                patterns
+                    .iter()
+                    .any(|pattern| utils::matches_pattern(pattern, name))
+            })
+        };
+        let data_name = data_folder
+            .and_then(Path::file_name)
+            .map(|name| name.to_string_lossy());
+        Ok(
+            if data_name
+                .is_some_and(|data| names.iter().any(|name| name.eq_ignore_ascii_case(&data)))
+            {
+                Some(RootKind::Game)
+            } else if matches(&self.data_entries) {
+                Some(RootKind::Data)
+            } else if matches(&self.root_entries) {
+                Some(RootKind::Game)
+            } else {
+                None
+            },
+        )
+    }
+    /// Find the real root of a mod, relative to the top folder
+    ///
+    /// Wrapper folders, which hold a single folder and no files, are descended through until
+    /// the rules recognize a folder's entries. Files next to a folder, like a BAIN package's
+    /// wizard.txt, mean the folder is not a wrapper. The kind is `None` if no folder was
+    /// recognized.
+    pub fn find_root(
+        &self,
+        top: &Path,
+        data_folder: Option<&Path>,
+    ) -> io::Result<(PathBuf, Option<RootKind>)> {
+        let mut root = PathBuf::new();
+        loop {
+            let dir = top.join(&root);
+            if let Some(kind) = self.classify(&dir, data_folder)? {
+                return Ok((root, Some(kind)));
+            }
+            let mut entries = fs::read_dir(&dir)?.filter_map(Result::ok);
+            match (entries.next(), entries.next()) {
+                (Some(only), None) if only.path().is_dir() => root.push(only.file_name()),
+                _ => return Ok((root, None)),
+            }
+        }
+    }
+}
+
+/// How the case of deployed files and folders is chosen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CasePolicy {
+    /// Create new entries in lowercase
+    Lowercase,
+    /// Create new entries in the case the mod uses
+    #[default]
+    MatchExisting,
+    /// Use the mod's paths exactly, even if that puts folders that differ only in case side
+    /// by side
+    Preserve,
+}
+
+/// Maps deployed paths onto existing entries in the game folder, ignoring case
+///
+/// Entries that are resolved but do not exist yet are remembered, so paths from different
+/// mods that differ only in case end up in the same place.
+pub struct CaseMap<'a> {
+    game_folder: &'a Path,
+    policy: CasePolicy,
+    /// The names in each resolved folder by their lowercase names
+    entries: HashMap<PathBuf, HashMap<String, String>>,
+}
+
+impl<'a> CaseMap<'a> {
+    pub fn new(game_folder: &'a Path, policy: CasePolicy) -> Self {
+        CaseMap {
+            game_folder,
+            policy,
+            entries: HashMap::new(),
+        }
+    }
+    /// Get the path that a path relative to the game folder should be deployed to
+    pub fn resolve(&mut self, path: &Path) -> PathBuf {
+        if self.policy == CasePolicy::Preserve {
+            return path.to_path_buf();
+        }
+        let mut resolved = PathBuf::new();
+        for part in path.iter() {
+            let name = part.to_string_lossy();
+            let game_folder = self.game_folder;
+            let entries = self
+                .entries
+                .entry(resolved.clone())
+                .or_insert_with(|| list_dir(&game_folder.join(&resolved)));
+            let policy = self.policy;
+            let actual = entries.entry(name.to_lowercase()).or_insert_with(|| {
+                if policy == CasePolicy::Lowercase {
+                    name.to_lowercase()
+                } else {
+                    name.clone().into_owned()
+                }
+            });
+            resolved.push(actual);
+        }
+        resolved
+    }
+}
+
+/// Get the names in a folder by their lowercase names
+fn list_dir(dir: &Path) -> HashMap<String, String> {
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            (name.to_lowercase(), name)
+        })
+        .collect()
+}
@@ -43,3 +43,11 @@ pub fn archives_dir(game: &str) -> crate::Result<PathBuf> {
 pub fn extracted_dir(game: &str, mod_name: &str) -> crate::Result<PathBuf> {
     game_dir(game).and_then(|game| game.join("extracted").join(mod_name).and_create_dirs())
 }
+
+pub fn backup_dir(game: &str) -> crate::Result<PathBuf> {
+    game_dir(game).and_then(|game| game.join("backup").and_create_dirs())
+}
+
+pub fn temp_dir(name: &str) -> crate::Result<PathBuf> {
+    clim_dir().and_then(|clim| clim.join("temp").join(name).and_create_dirs())
+}
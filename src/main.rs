@@ -1,19 +1,27 @@
 mod utils;
 
 mod app;
+mod bain;
 mod error;
+mod extract;
 mod fomod;
 mod game;
+mod layout;
 mod library;
+mod manifest;
+mod plan;
+mod progress;
 use app::*;
 
 use std::{
     collections::HashSet,
     fs,
     io::{stdin, BufRead},
+    path::Path,
     sync::{Arc, Mutex},
 };
 
+use indexmap::IndexMap;
 use structopt::StructOpt;
 
 use error::{Error, Result};
@@ -41,19 +49,46 @@ fn run() -> Result<()> {
         } => {
             gc.init_game(name, game_folder, data, plugins, exe)?;
         }
-        App::Go => gc.active_game()?.go()?,
+        App::Go {
+            install,
+            jobs,
+            dry_run,
+            json,
+        } => {
+            let mut game = gc.active_game()?;
+            if dry_run {
+                game.discard_changes();
+                let plan = game.dry_run()?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&plan)?);
+                } else {
+                    plan.print();
+                }
+            } else {
+                game.go(
+                    &install.options()?,
+                    jobs.unwrap_or_else(utils::default_jobs),
+                )?
+            }
+        }
         App::Add {
             archives,
             r#move,
             enable,
         } => gc.active_game()?.add(&archives, r#move, enable)?,
-        App::Enable { names, all } => {
+        App::Enable {
+            names,
+            all,
+            install,
+            jobs,
+        } => {
+            let options = install.options()?;
             let mut game = gc.active_game()?;
             if all {
-                game.enable_all()?;
+                game.enable_all(&options, jobs.unwrap_or_else(utils::default_jobs))?;
             } else {
                 for name in names {
-                    game.enable(&name)?;
+                    game.enable(&name, &options)?;
                 }
             }
         }
@@ -69,10 +104,163 @@ fn run() -> Result<()> {
         }
         App::Mods => {
             for (mod_name, mm) in &gc.active_game()?.config.mods {
+                let version = mm
+                    .info
+                    .as_ref()
+                    .and_then(|info| info.version.as_ref())
+                    .map(|version| format!(" ({})", version))
+                    .unwrap_or_default();
                 if mm.enabled {
-                    colorln!(normal, "{}", mod_name);
+                    colorln!(normal, "{}{}", mod_name, version);
                 } else {
-                    colorln!(dimmed, "{}", mod_name);
+                    colorln!(dimmed, "{}{}", mod_name, version);
+                }
+            }
+        }
+        App::Status => {
+            let mut stale = 0;
+            for (mod_name, mm) in &gc.active_game()?.config.mods {
+                let problem = if mm.extracted.is_none() {
+                    if !mm.enabled {
+                        continue;
+                    }
+                    "not extracted yet"
+                } else {
+                    match mm.archive_state()? {
+                        extract::ArchiveState::Unchanged | extract::ArchiveState::Unknown => {
+                            continue
+                        }
+                        extract::ArchiveState::Changed => "archive changed since extraction",
+                        extract::ArchiveState::Missing => "archive is missing",
+                    }
+                };
+                colorln!(yellow, "{}: {}", mod_name, problem);
+                stale += 1;
+            }
+            if stale == 0 {
+                colorln!(green, "All extractions are up to date");
+            }
+        }
+        App::Conflicts { name } => {
+            let mut game = gc.active_game()?;
+            for (mod_name, mm) in &game.config.mods {
+                if mm.enabled && mm.files.is_none() {
+                    colorln!(
+                        yellow,
+                        "{:?} has not been installed yet, so its files are not included",
+                        mod_name
+                    );
+                }
+            }
+            let conflicts = game.conflicts()?;
+            if let Some(name) = name {
+                let mod_name = game.get_mod(&name)?.0.to_string();
+                let mut overwrites: IndexMap<&str, Vec<&Path>> = IndexMap::new();
+                let mut overwritten_by: IndexMap<&str, Vec<&Path>> = IndexMap::new();
+                for conflict in &conflicts {
+                    if !conflict.mods.contains(&mod_name) {
+                        continue;
+                    }
+                    if conflict.winner() == mod_name {
+                        for other in conflict.mods.iter().filter(|&other| other != &mod_name) {
+                            overwrites.entry(other).or_default().push(&conflict.target);
+                        }
+                    } else {
+                        overwritten_by
+                            .entry(conflict.winner())
+                            .or_default()
+                            .push(&conflict.target);
+                    }
+                }
+                for (label, others) in [
+                    ("Overwrites", overwrites),
+                    ("Overwritten by", overwritten_by),
+                ] {
+                    if others.is_empty() {
+                        continue;
+                    }
+                    colorln!(bold, "{}", label);
+                    for (other, targets) in others {
+                        println!("  {} ({} files)", other, targets.len());
+                        for target in targets {
+                            colorln!(dimmed, "    {}", target.to_string_lossy());
+                        }
+                    }
+                }
+                if !conflicts
+                    .iter()
+                    .any(|conflict| conflict.mods.contains(&mod_name))
+                {
+                    colorln!(green, "{} has no conflicts", mod_name);
+                }
+            } else {
+                for conflict in &conflicts {
+                    colorln!(bold, "{}", conflict.target.to_string_lossy());
+                    for (i, mod_name) in conflict.mods.iter().enumerate() {
+                        if i == conflict.winner {
+                            colorln!(green, "  {} (wins)", mod_name);
+                        } else {
+                            println!("  {}", mod_name);
+                        }
+                    }
+                }
+                if conflicts.is_empty() {
+                    colorln!(green, "No conflicts");
+                } else {
+                    println!("{} conflicting files", conflicts.len());
+                }
+            }
+        }
+        App::Hide {
+            name,
+            patterns,
+            remove,
+        } => gc.active_game()?.hide(&name, patterns, remove)?,
+        App::Prefer {
+            name,
+            patterns,
+            remove,
+        } => gc.active_game()?.prefer(&name, patterns, remove)?,
+        App::Info { name } => {
+            let mut game = gc.active_game()?;
+            let (mod_name, mm) = game.get_mod(&name)?;
+            // Mods extracted before info was recorded
+            if let (None, Some(extracted)) = (&mm.info, &mm.extracted) {
+                mm.info = fomod::ModInfo::find(extracted)?;
+            }
+            colorln!(bold, "{}", mod_name);
+            println!("Enabled: {}", if mm.enabled { "yes" } else { "no" });
+            println!("Archive: {}", mm.archive.to_string_lossy());
+            if let Some(extracted) = &mm.extracted {
+                println!("Extracted: {}", extracted.to_string_lossy());
+            }
+            if !mm.hidden.is_empty() {
+                println!("Hidden: {}", mm.hidden.join(", "));
+            }
+            if !mm.preferred.is_empty() {
+                println!("Preferred: {}", mm.preferred.join(", "));
+            }
+            if let Some(selection) = &mm.bain {
+                println!("Sub-packages: {}", selection.sub_packages.join(", "));
+            }
+            if let Some(info) = &mm.info {
+                let fields = [
+                    ("Name", &info.name),
+                    ("Version", &info.version),
+                    ("Author", &info.author),
+                    ("Website", &info.website),
+                ];
+                for (label, value) in &fields {
+                    if let Some(value) = value {
+                        println!("{}: {}", label, value);
+                    }
+                }
+                if !info.groups.is_empty() {
+                    println!("Groups: {}", info.groups.join(", "));
+                }
+                if let Some(description) = &info.description {
+                    println!();
+                    println!("{}", description);
                 }
             }
         }
@@ -141,6 +329,28 @@ fn run() -> Result<()> {
                 return Err(Error::NoGameExectuable);
             }
         }
+        App::Fomod { sub } => match sub {
+            FomodSubcommand::Check { path } => {
+                let problems = if path.is_dir() {
+                    fomod::check(&path)?
+                } else {
+                    let temp = library::temp_dir("check")?;
+                    let _ = fs::remove_dir_all(&temp);
+                    let problems = extract::extract_archive(&path, &temp, &mut |_| {})
+                        .and_then(|_| fomod::check(&temp));
+                    let _ = fs::remove_dir_all(&temp);
+                    problems?
+                };
+                for problem in &problems {
+                    colorln!(yellow, "{}", problem);
+                }
+                if problems.is_empty() {
+                    colorln!(green, "No problems found");
+                } else {
+                    colorln!(red, "{} problem(s) found", problems.len());
+                }
+            }
+        },
         App::Watch { folder, enable } => {
             use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
             let path = if let Some(folder) = folder {
@@ -158,13 +368,12 @@ fn run() -> Result<()> {
                         return;
                     };
                     let path = event.paths[0].clone();
-                    if path.extension().map_or(false, |ext| ext != "crdownload")
+                    if path.extension().is_some_and(|ext| ext != "crdownload")
                         && !matches!(event.kind, EventKind::Remove(_))
                     {
-                        if let Err(e) = gc
-                            .active_game()
-                            .and_then(|mut game| game.add(&[path.clone()], false, enable))
-                        {
+                        if let Err(e) = gc.active_game().and_then(|mut game| {
+                            game.add(std::slice::from_ref(&path), false, enable)
+                        }) {
                             println!("{}", e);
                         } else {
                             added_paths.lock().unwrap().insert(path);
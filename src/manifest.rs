@@ -0,0 +1,155 @@
+use std::{
+    fs, io, mem,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{colorln, game::DeploymentMethod, library, utils};
+
+const MANIFEST_FILE: &str = "manifest.toml";
+
+fn manifest_file(game: &str) -> crate::Result<PathBuf> {
+    library::game_dir(game).map(|game_dir| game_dir.join(MANIFEST_FILE))
+}
+
+/// A file that clim deployed to the game folder
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeployedFile {
+    /// The path relative to the game folder
+    pub target: PathBuf,
+    pub mod_name: String,
+    pub source: PathBuf,
+    pub method: DeploymentMethod,
+    /// The size of the deployed file, used to tell if it was replaced
+    pub size: u64,
+    /// The modification time of the deployed file in seconds since the Unix epoch
+    pub modified: u64,
+    /// Whether a file that was already in the game folder was moved to the backup folder
+    #[serde(default)]
+    pub backed_up: bool,
+}
+
+fn stamp(path: &Path) -> io::Result<(u64, u64)> {
+    let meta = fs::symlink_metadata(path)?;
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_secs());
+    Ok((meta.len(), modified))
+}
+
+impl DeployedFile {
+    /// Record a file that was just deployed
+    pub fn new(
+        game_folder: &Path,
+        target: PathBuf,
+        mod_name: &str,
+        source: PathBuf,
+        method: DeploymentMethod,
+        backed_up: bool,
+    ) -> io::Result<Self> {
+        let (size, modified) = stamp(&game_folder.join(&target))?;
+        Ok(DeployedFile {
+            target,
+            mod_name: mod_name.into(),
+            source,
+            method,
+            size,
+            modified,
+            backed_up,
+        })
+    }
+    /// Check if the file in the game folder is still the one clim deployed
+    pub fn is_unchanged(&self, game_folder: &Path) -> bool {
+        let path = game_folder.join(&self.target);
+        let is_link = fs::read_link(&path).is_ok_and(|link| link == self.source);
+        match self.method {
+            // The source may have been deleted, so only the link itself is checked
+            DeploymentMethod::Symlink if cfg!(unix) => is_link,
+            _ => !is_link && stamp(&path).is_ok_and(|stamp| stamp == (self.size, self.modified)),
+        }
+    }
+}
+
+/// Every file that clim has deployed for a game
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Manifest {
+    pub files: Vec<DeployedFile>,
+}
+
+impl Manifest {
+    /// Open a game's manifest, if it has one
+    pub fn open(game: &str) -> crate::Result<Option<Self>> {
+        match fs::read(manifest_file(game)?) {
+            Ok(bytes) => Ok(Some(toml::from_slice(&bytes)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+    pub fn save(&self, game: &str) -> crate::Result<()> {
+        let string = toml::to_string_pretty(self)?;
+        fs::write(manifest_file(game)?, &string).map_err(Into::into)
+    }
+    /// Remove the deployed files that match a predicate and restore the files they replaced
+    ///
+    /// Files that were changed since they were deployed were not created by clim, so they
+    /// are left alone.
+    pub fn undeploy<F>(
+        &mut self,
+        game_folder: &Path,
+        backup_dir: &Path,
+        mut remove: F,
+    ) -> crate::Result<()>
+    where
+        F: FnMut(&DeployedFile) -> bool,
+    {
+        let mut kept = Vec::new();
+        let mut files = mem::take(&mut self.files).into_iter();
+        let mut result = Ok(());
+        for file in &mut files {
+            if !remove(&file) {
+                kept.push(file);
+                continue;
+            }
+            if let Err(e) = undeploy_file(game_folder, backup_dir, &file) {
+                // Keep the rest of the manifest so that the files can still be undeployed
+                kept.push(file);
+                result = Err(e);
+                break;
+            }
+        }
+        kept.extend(files);
+        self.files = kept;
+        result
+    }
+}
+
+/// Remove a deployed file and restore the file it replaced
+fn undeploy_file(game_folder: &Path, backup_dir: &Path, file: &DeployedFile) -> crate::Result<()> {
+    let path = game_folder.join(&file.target);
+    let backup = backup_dir.join(&file.target);
+    if fs::symlink_metadata(&path).is_ok() {
+        if !file.is_unchanged(game_folder) {
+            colorln!(
+                yellow,
+                "Not removing {:?} because it was changed outside of clim",
+                file.target
+            );
+            if file.backed_up {
+                colorln!(yellow, "The original is in {:?}", backup);
+            }
+            return Ok(());
+        }
+        utils::remove_path(game_folder, &file.target)?;
+    }
+    if file.backed_up {
+        utils::move_file(&backup, &path)?;
+        // Clean up the folders in the backup folder
+        utils::remove_path(backup_dir, &file.target)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,144 @@
+use std::path::PathBuf;
+
+use itertools::Itertools;
+use serde_derive::Serialize;
+
+use crate::{colorln, game::Conflict};
+
+/// What deploying does to a file in the game folder
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Create,
+    Replace,
+    Remove,
+}
+
+/// A change to a file in the game folder
+#[derive(Debug, Clone, Serialize)]
+pub struct FileChange {
+    pub action: Action,
+    /// The path relative to the game folder
+    pub target: PathBuf,
+    /// The mod that provides the file, or that provided it if it is removed
+    pub mod_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<PathBuf>,
+    /// Whether a file that clim did not deploy is moved to the backup folder
+    pub backup: bool,
+    /// The files of other mods that this one overwrites
+    #[serde(skip)]
+    pub overwritten: Vec<PathBuf>,
+}
+
+/// What `go` does to the game folder and plugins file
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Plan {
+    /// Mods that are extracted first
+    pub extract: Vec<String>,
+    /// Mods whose installers run first, so their files are not included
+    pub install: Vec<String>,
+    pub files: Vec<FileChange>,
+    /// The number of deployed files that stay as they are
+    pub unchanged: usize,
+    pub plugins_added: Vec<String>,
+    pub plugins_removed: Vec<String>,
+    pub conflicts: Vec<Conflict>,
+    /// Files that cannot be deployed
+    pub errors: Vec<String>,
+    /// Deployed files that are removed before any are linked, including replaced ones
+    #[serde(skip)]
+    pub outdated: Vec<PathBuf>,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.extract.is_empty()
+            && self.install.is_empty()
+            && self.files.is_empty()
+            && self.plugins_added.is_empty()
+            && self.plugins_removed.is_empty()
+            && self.errors.is_empty()
+    }
+    pub fn print(&self) {
+        if !self.extract.is_empty() {
+            colorln!(bold, "Extract");
+            for mod_name in &self.extract {
+                println!("  {}", mod_name);
+            }
+        }
+        if !self.install.is_empty() {
+            colorln!(bold, "Install (files not included)");
+            for mod_name in &self.install {
+                println!("  {}", mod_name);
+            }
+        }
+        if !self.files.is_empty() {
+            colorln!(bold, "Files");
+            for change in &self.files {
+                let target = change.target.to_string_lossy();
+                let backup = if change.backup {
+                    ", backing up the original"
+                } else {
+                    ""
+                };
+                match change.action {
+                    Action::Create => colorln!(green, "  + {} ({})", target, change.mod_name),
+                    Action::Replace => {
+                        colorln!(yellow, "  ~ {} ({}{})", target, change.mod_name, backup)
+                    }
+                    Action::Remove => colorln!(red, "  - {} ({})", target, change.mod_name),
+                }
+            }
+        }
+        if !self.plugins_added.is_empty() || !self.plugins_removed.is_empty() {
+            colorln!(bold, "Plugins");
+            for line in &self.plugins_added {
+                colorln!(green, "  + {}", line);
+            }
+            for line in &self.plugins_removed {
+                colorln!(red, "  - {}", line);
+            }
+        }
+        if !self.conflicts.is_empty() {
+            colorln!(bold, "Conflicts");
+            for conflict in &self.conflicts {
+                println!(
+                    "  {}: {} wins over {}",
+                    conflict.target.to_string_lossy(),
+                    conflict.winner(),
+                    conflict
+                        .mods
+                        .iter()
+                        .enumerate()
+                        .filter(|&(i, _)| i != conflict.winner)
+                        .map(|(_, mod_name)| mod_name)
+                        .join(", ")
+                );
+            }
+        }
+        if !self.errors.is_empty() {
+            colorln!(bold, "Errors");
+            for error in &self.errors {
+                colorln!(red, "  {}", error);
+            }
+        }
+        let count = |action| {
+            self.files
+                .iter()
+                .filter(|change| change.action == action)
+                .count()
+        };
+        if self.is_empty() {
+            colorln!(green, "Everything is already deployed");
+        } else {
+            println!(
+                "{} to create, {} to replace, {} to remove, {} unchanged",
+                count(Action::Create),
+                count(Action::Replace),
+                count(Action::Remove),
+                self.unchanged
+            );
+        }
+    }
+}
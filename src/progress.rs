@@ -0,0 +1,155 @@
+use std::{
+    io::{self, Write},
+    sync::Mutex,
+};
+
+use indexmap::IndexMap;
+
+use crate::colorln;
+
+/// How far work on one mod has gotten
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Status {
+    pub files: usize,
+    pub total_files: usize,
+    pub bytes: u64,
+}
+
+struct State {
+    done: usize,
+    /// The latest status of each mod that has started
+    mods: IndexMap<String, Status>,
+    /// The mod that was updated last
+    current: Option<String>,
+    line_len: usize,
+}
+
+/// Shows the progress of work on several mods at once on a single status line
+pub struct Progress {
+    /// Describes work in progress, like "Extracting"
+    verb: &'static str,
+    /// Describes finished work, like "Extracted"
+    past: &'static str,
+    total: usize,
+    /// Whether to print a line for each finished mod
+    log_finished: bool,
+    state: Mutex<State>,
+}
+
+/// Format a number of bytes with a unit
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+impl Progress {
+    pub fn new(verb: &'static str, past: &'static str, total: usize, log_finished: bool) -> Self {
+        Progress {
+            verb,
+            past,
+            total,
+            log_finished,
+            state: Mutex::new(State {
+                done: 0,
+                mods: IndexMap::new(),
+                current: None,
+                line_len: 0,
+            }),
+        }
+    }
+    pub fn update(&self, mod_name: &str, status: Status) {
+        let mut state = self.state.lock().unwrap();
+        state.mods.insert(mod_name.into(), status);
+        state.current = Some(mod_name.into());
+        self.render(&mut state);
+    }
+    pub fn finish(&self, mod_name: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.done += 1;
+        if state.current.as_deref() == Some(mod_name) {
+            state.current = None;
+        }
+        if self.log_finished {
+            let status = state.mods.get(mod_name).copied().unwrap_or_default();
+            self.clear(&mut state);
+            println!(
+                "{} {:?} ({} files, {})",
+                self.past,
+                mod_name,
+                status.files,
+                format_bytes(status.bytes)
+            );
+        }
+        self.render(&mut state);
+    }
+    /// Print a message above the status line
+    pub fn message(&self, message: &str) {
+        let mut state = self.state.lock().unwrap();
+        self.clear(&mut state);
+        colorln!(yellow, "{}", message);
+        self.render(&mut state);
+    }
+    /// Replace the status line with a summary
+    pub fn done(&self) {
+        let mut state = self.state.lock().unwrap();
+        self.clear(&mut state);
+        let (files, bytes) = totals(&state);
+        colorln!(
+            green,
+            "{} {} mods ({} files, {})",
+            self.past,
+            state.done,
+            files,
+            format_bytes(bytes)
+        );
+    }
+    /// Remove the status line
+    pub fn clear_line(&self) {
+        self.clear(&mut self.state.lock().unwrap());
+    }
+    fn clear(&self, state: &mut State) {
+        print!("\r{}\r", " ".repeat(state.line_len));
+        state.line_len = 0;
+    }
+    fn render(&self, state: &mut State) {
+        let (files, bytes) = totals(state);
+        let mut line = format!(
+            "{} {}/{} mods, {} files, {}",
+            self.verb,
+            state.done,
+            self.total,
+            files,
+            format_bytes(bytes)
+        );
+        if let Some((name, status)) = state
+            .current
+            .as_ref()
+            .and_then(|name| Some((name, state.mods.get(name)?)))
+        {
+            line.push_str(&format!(
+                " | {:?} {}/{}",
+                name, status.files, status.total_files
+            ));
+        }
+        let padding = state.line_len.saturating_sub(line.chars().count());
+        print!("\r{}{}", line, " ".repeat(padding));
+        let _ = io::stdout().flush();
+        state.line_len = line.chars().count();
+    }
+}
+
+fn totals(state: &State) -> (usize, u64) {
+    state.mods.values().fold((0, 0), |(files, bytes), status| {
+        (files + status.files, bytes + status.bytes)
+    })
+}
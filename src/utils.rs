@@ -1,8 +1,13 @@
 use std::{
-    fs, io,
+    fs,
+    io::{self, stdin, BufRead},
     path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
 };
 
+use walkdir::WalkDir;
+
 #[macro_export]
 macro_rules! colorln {
     ($color:ident, $format:literal $(, $item:expr)* $(,)? ) => {
@@ -18,26 +23,135 @@ macro_rules! waitln {
     }
 }
 
-pub fn capitalize_path(top: &Path, path: &Path) -> PathBuf {
-    let diff = pathdiff::diff_paths(path, top).unwrap();
-    let capped: PathBuf = diff
-        .iter()
-        .map(|part| {
-            part.to_string_lossy()
-                .chars()
-                .enumerate()
-                .flat_map(|(i, c)| {
-                    if i == 0 {
-                        c.to_uppercase().collect::<Vec<_>>()
-                    } else {
-                        vec![c]
-                    }
-                })
-                .collect::<String>()
+/// Print a message and read a line of input
+pub fn prompt<S>(message: S) -> io::Result<String>
+where
+    S: AsRef<str>,
+{
+    waitln!("{}", message.as_ref());
+    let line = stdin().lock().lines().next().unwrap_or_else(|| {
+        Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Input ended before a response was given",
+        ))
+    })?;
+    Ok(line.trim().into())
+}
+
+/// Parse a list of 1-based option numbers into 0-based indices
+fn parse_selection(input: &str, count: usize) -> Option<Vec<usize>> {
+    let mut selected = Vec::new();
+    for part in input
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|part| !part.is_empty())
+    {
+        let n: usize = part.parse().ok()?;
+        if n == 0 || n > count {
+            return None;
+        }
+        if !selected.contains(&(n - 1)) {
+            selected.push(n - 1);
+        }
+    }
+    selected.sort_unstable();
+    Some(selected)
+}
+
+/// Ask for some of `count` numbered options until valid numbers are given
+pub fn prompt_selection(count: usize, defaults: &[usize]) -> io::Result<Vec<usize>> {
+    loop {
+        let input =
+            prompt("Selection (numbers separated by spaces, \"none\", or nothing for defaults): ")?;
+        let selected = if input.is_empty() {
+            Some(defaults.to_vec())
+        } else if input.eq_ignore_ascii_case("none") {
+            Some(Vec::new())
+        } else {
+            parse_selection(&input, count)
+        };
+        if let Some(selected) = selected {
+            return Ok(selected);
+        }
+        colorln!(yellow, "Please enter numbers from 1 to {}", count);
+    }
+}
+
+/// Check if a file is a plugin by its extension
+pub fn is_plugin<P>(path: P) -> bool
+where
+    P: AsRef<Path>,
+{
+    path.as_ref().extension().is_some_and(|ext| {
+        ["esp", "esm", "esl"].contains(&ext.to_string_lossy().to_lowercase().as_str())
+    })
+}
+
+/// Check if a name matches a pattern, ignoring case
+///
+/// `*` matches any number of characters and `?` matches one.
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    fn matches(pattern: &[char], name: &[char]) -> bool {
+        match pattern.split_first() {
+            None => name.is_empty(),
+            Some(('*', rest)) => (0..=name.len()).any(|i| matches(rest, &name[i..])),
+            Some((&c, rest)) => name.split_first().is_some_and(|(&n, name)| {
+                (c == '?' || c.to_lowercase().eq(n.to_lowercase())) && matches(rest, name)
+            }),
+        }
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches(&pattern, &name)
+}
+
+/// Find the first file with the given name, ignoring case, in a directory tree
+pub fn find_file<P>(top: P, name: &str) -> Option<PathBuf>
+where
+    P: AsRef<Path>,
+{
+    WalkDir::new(top)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .find(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .eq_ignore_ascii_case(name)
         })
-        .map(PathBuf::from)
-        .collect();
-    top.join(capped)
+        .map(walkdir::DirEntry::into_path)
+}
+
+/// Find an existing path relative to a base directory, ignoring the case of each component
+pub fn find_case_insensitive<P, Q>(base: P, relative: Q) -> Option<PathBuf>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let mut path = base.as_ref().to_path_buf();
+    for part in relative.as_ref().iter() {
+        let exact = path.join(part);
+        if exact.exists() {
+            path = exact;
+            continue;
+        }
+        let part = part.to_string_lossy();
+        path = fs::read_dir(&path)
+            .ok()?
+            .filter_map(Result::ok)
+            .find(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .eq_ignore_ascii_case(&part)
+            })?
+            .path();
+    }
+    if path.exists() {
+        Some(path)
+    } else {
+        None
+    }
 }
 
 pub fn remove_path<P, Q>(top: P, name: Q) -> io::Result<()>
@@ -47,9 +161,9 @@ where
 {
     let top = top.as_ref();
     let mut name = name.as_ref();
-    // Delete file
+    // Delete file, or a link to one that no longer exists
     let path = top.join(name);
-    if path.is_file() {
+    if fs::symlink_metadata(&path).is_ok_and(|meta| !meta.is_dir()) {
         fs::remove_file(path)?;
     }
     // Delete empty folders
@@ -65,15 +179,166 @@ where
     Ok(())
 }
 
-pub fn create_dirs<P>(path: P) -> io::Result<()>
+/// Move a file, copying it if it is moved to another drive
+pub fn move_file<P, Q>(from: P, to: Q) -> io::Result<()>
 where
     P: AsRef<Path>,
+    Q: AsRef<Path>,
 {
-    let path = path.as_ref();
-    if path.extension().is_some() {
-        fs::create_dir_all(path.parent().unwrap())?;
-    } else {
-        fs::create_dir_all(path)?;
+    let (from, to) = (from.as_ref(), to.as_ref());
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+/// Check if two paths are on the same device
+pub fn same_device<P, Q>(a: P, b: Q) -> bool
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        match (fs::metadata(a), fs::metadata(b)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev(),
+            _ => false,
+        }
+    }
+    // Linking fails across devices, so assume the same device and let it fail
+    #[cfg(not(unix))]
+    {
+        let _ = (a, b);
+        true
+    }
+}
+
+/// Make a copy-on-write clone of a file
+///
+/// This only works on filesystems that support it, like btrfs and xfs.
+#[cfg(target_os = "linux")]
+pub fn reflink<P, Q>(source: P, target: Q) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    use std::os::unix::io::AsRawFd;
+    let source = fs::File::open(source)?;
+    let target = target.as_ref();
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(target)?;
+    // Safety: both file descriptors are open for the duration of the call
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) } == -1 {
+        let e = io::Error::last_os_error();
+        drop(file);
+        let _ = fs::remove_file(target);
+        return Err(e);
     }
     Ok(())
 }
+
+#[cfg(not(target_os = "linux"))]
+pub fn reflink<P, Q>(_source: P, _target: Q) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    Err(io::Error::other(
+        "Reflinks are not supported on this platform",
+    ))
+}
+
+/// Check if two files have the same contents
+pub fn same_contents<P, Q>(a: P, b: Q) -> io::Result<bool>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let (a, b) = (a.as_ref(), b.as_ref());
+    if a == b {
+        return Ok(true);
+    }
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+    let mut a = io::BufReader::new(fs::File::open(a)?);
+    let mut b = io::BufReader::new(fs::File::open(b)?);
+    loop {
+        let a_buf = a.fill_buf()?;
+        let b_buf = b.fill_buf()?;
+        let len = a_buf.len().min(b_buf.len());
+        if len == 0 {
+            return Ok(a_buf.len() == b_buf.len());
+        }
+        if a_buf[..len] != b_buf[..len] {
+            return Ok(false);
+        }
+        a.consume(len);
+        b.consume(len);
+    }
+}
+
+/// Check if two paths lead to the same file, through links or otherwise
+pub fn same_file<P, Q>(a: P, b: Q) -> bool
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        match (fs::metadata(a), fs::metadata(b)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        match (fs::canonicalize(a), fs::canonicalize(b)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Get the default number of worker threads, which is the number of CPUs
+pub fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(4, |n| n.get())
+}
+
+/// Run a function on each item with up to `jobs` threads, stopping at the first error
+pub fn parallel<T, F>(items: Vec<T>, jobs: usize, f: F) -> crate::Result<()>
+where
+    T: Send,
+    F: Fn(T) -> crate::Result<()> + Sync,
+{
+    let jobs = jobs.clamp(1, items.len().max(1));
+    let queue = Mutex::new(items.into_iter());
+    let error = Mutex::new(None);
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                if error.lock().unwrap().is_some() {
+                    break;
+                }
+                let item = if let Some(item) = queue.lock().unwrap().next() {
+                    item
+                } else {
+                    break;
+                };
+                if let Err(e) = f(item) {
+                    error.lock().unwrap().get_or_insert(e);
+                    break;
+                }
+            });
+        }
+    });
+    error.into_inner().unwrap().map_or(Ok(()), Err)
+}